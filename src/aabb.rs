@@ -0,0 +1,81 @@
+use crate::vec::{Point3, Vec3};
+
+// An axis-aligned bounding box.
+#[derive(Clone, Copy)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Aabb {
+    // The box with opposite corners `a` and `b`, in any order.
+    pub fn new(a: Point3, b: Point3) -> Aabb {
+        Aabb {
+            min: Point3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)),
+            max: Point3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z)),
+        }
+    }
+
+    // The smallest box enclosing both boxes.
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Aabb::new(self.min, other.min).min,
+            max: Aabb::new(self.max, other.max).max,
+        }
+    }
+
+    // The box moved by `offset`.
+    pub fn offset(&self, offset: Vec3) -> Aabb {
+        Aabb {
+            min: self.min + offset,
+            max: self.max + offset,
+        }
+    }
+
+    // The box grown by `margin` on every side.
+    pub fn pad(&self, margin: f64) -> Aabb {
+        let margin = Vec3::new(margin, margin, margin);
+        Aabb {
+            min: self.min - margin,
+            max: self.max + margin,
+        }
+    }
+
+    // The eight corners of the box.
+    pub fn corners(&self) -> [Point3; 8] {
+        std::array::from_fn(|corner| {
+            let pick = |axis: usize| {
+                if (corner >> axis) & 1 == 0 {
+                    self.min[axis]
+                } else {
+                    self.max[axis]
+                }
+            };
+            Point3::new(pick(0), pick(1), pick(2))
+        })
+    }
+
+    pub fn center(&self) -> Point3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn diagonal(&self) -> Vec3 {
+        self.max - self.min
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn union_encloses_both_boxes() {
+        let a = Aabb::new(Point3::new(1.0, -2.0, 0.0), Point3::new(-1.0, 0.0, 3.0));
+        let b = Aabb::new(Point3::new(2.0, 1.0, 1.0), Point3::new(4.0, 5.0, 2.0));
+        let components = |p: Point3| (p.x, p.y, p.z);
+        for union in [a.union(&b), b.union(&a)] {
+            assert_eq!(components(union.min), (-1.0, -2.0, 0.0));
+            assert_eq!(components(union.max), (4.0, 5.0, 3.0));
+        }
+    }
+}
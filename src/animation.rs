@@ -0,0 +1,153 @@
+use cgmath::{InnerSpace, Quaternion};
+
+use crate::camera::Camera;
+use crate::hittable::Hittable;
+use crate::integrator::Integrator;
+use crate::scene::Scene;
+use crate::vec::Vec3;
+
+// Values that can be blended between keyframes.
+pub trait Lerp {
+    // The value `t` of the way from `self` to `other`.
+    fn lerp(&self, other: &Self, t: f64) -> Self;
+}
+
+impl Lerp for f64 {
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        self + (other - self) * t
+    }
+}
+
+// Also covers colors.
+impl Lerp for Vec3 {
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        *self + (*other - *self) * t
+    }
+}
+
+// Rotations are blended along the shorter way round, and renormalized.
+impl Lerp for Quaternion<f64> {
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        let other = if self.dot(*other) < 0.0 {
+            -other
+        } else {
+            *other
+        };
+        (self * (1.0 - t) + other * t).normalize()
+    }
+}
+
+// A value that changes over time, such as a camera position, an object's offset or a material's
+// roughness. It's interpolated linearly between keyframes, and holds still before the first
+// and after the last.
+pub struct Keyframes<T> {
+    // Times in seconds and the values at them, in order of time.
+    keys: Vec<(f64, T)>,
+}
+
+impl<T: Lerp + Clone> Keyframes<T> {
+    pub fn new(mut keys: Vec<(f64, T)>) -> Keyframes<T> {
+        assert!(!keys.is_empty(), "Keyframes need at least one key");
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Keyframes { keys }
+    }
+
+    pub fn at(&self, time: f64) -> T {
+        let next = self.keys.partition_point(|(key_time, _)| *key_time <= time);
+        if next == 0 {
+            return self.keys[0].1.clone();
+        }
+        if next == self.keys.len() {
+            return self.keys[next - 1].1.clone();
+        }
+
+        let (time0, value0) = &self.keys[next - 1];
+        let (time1, value1) = &self.keys[next];
+        value0.lerp(value1, (time - time0) / (time1 - time0))
+    }
+
+    pub fn keys(&self) -> &[(f64, T)] {
+        &self.keys
+    }
+}
+
+// Everything needed to render one frame of a sequence.
+pub struct Frame {
+    pub scene: Scene,
+    pub camera: Camera,
+    pub integrator: Box<dyn Integrator>,
+}
+
+// An animation rendered as `frames` images, `frame_rate` per second.
+pub struct Sequence {
+    pub frames: u32,
+    pub frame_rate: f64,
+}
+
+impl Sequence {
+    // Renders every frame, saving them as frame_0001.png, frame_0002.png and so on. `frame`
+    // builds each one from its time in seconds, starting at 0.
+    pub fn render(&self, frame: impl Fn(f64) -> Frame) {
+        for index in 0..self.frames {
+            let time = f64::from(index) / self.frame_rate;
+            let Frame {
+                scene,
+                camera,
+                integrator,
+            } = frame(time);
+            camera.render_to(&scene, integrator.as_ref(), &frame_filename(index));
+        }
+    }
+}
+
+// A camera orbiting once around everything in the scene over `frames` frames, at `elevation`
+// degrees above it and far enough away to keep it all in view, for reviewing a model from
+// every side.
+pub struct Turntable {
+    pub frames: u32,
+    pub elevation: f64,
+}
+
+impl Turntable {
+    // Renders every frame through `camera`, whose resolution and field of view are kept, and
+    // saves them as frame_0001.png, frame_0002.png and so on.
+    pub fn render(&self, scene: &Scene, camera: &Camera, integrator: &dyn Integrator) {
+        let bounds = scene.world.bounding_box().expect("Nothing to frame");
+        for index in 0..self.frames {
+            let azimuth = 360.0 * f64::from(index) / f64::from(self.frames);
+            camera
+                .clone()
+                .with_orbit(&bounds, azimuth, self.elevation)
+                .render_to(scene, integrator, &frame_filename(index));
+        }
+    }
+}
+
+// Name of the image of the frame at `index`, counting from 0.
+fn frame_filename(index: u32) -> String {
+    format!("frame_{:04}.png", index + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyframes_interpolate_and_hold_still_outside_their_range() {
+        let keyframes = Keyframes::new(vec![(2.0, 10.0), (0.0, 0.0), (1.0, 4.0)]);
+        assert_eq!(keyframes.at(-1.0), 0.0);
+        assert_eq!(keyframes.at(0.5), 2.0);
+        assert_eq!(keyframes.at(1.5), 7.0);
+        assert_eq!(keyframes.at(3.0), 10.0);
+    }
+
+    #[test]
+    fn duplicate_keys_step_between_values() {
+        // Stepped glTF samplers repeat a time to jump from one value to the next.
+        let keyframes = Keyframes::new(vec![(0.0, 0.0), (1.0, 0.0), (1.0, 5.0), (2.0, 5.0)]);
+        assert_eq!(keyframes.at(0.999), 0.0);
+        assert_eq!(keyframes.at(1.0), 5.0);
+        assert_eq!(keyframes.at(1.5), 5.0);
+        assert!(keyframes.keys().iter().all(|(_, value)| value.is_finite()));
+    }
+}
@@ -0,0 +1,619 @@
+use crate::camera::Projection;
+use crate::color::Color;
+use crate::hittable::HitRecord;
+use crate::integrator::{russian_roulette, Integrator, RenderContext, MAX_DEPTH};
+use crate::light::Light;
+use crate::material::emitted_radiance;
+use crate::ray::Ray;
+use crate::spectrum::upsample;
+use crate::vec::{Point3, Vec3};
+
+// Bidirectional path tracing (Veach 1997). Every camera sample traces one subpath from the
+// camera and another from a light, and connects every prefix of one to every prefix of the
+// other. Each way of building a path is weighted against all the others that could have built
+// it with the power heuristic, so light that's hard to find from the camera (say, through a
+// small opening) is picked up from the light's side instead. Connections straight to the
+// camera land on other pixels, and are splatted onto the film.
+//
+// NOTE: light subpaths ignore the scaling of radiance by refraction, and directional lights,
+// which can't emit rays, are only found by sampling them from the camera subpath.
+pub struct Bdpt;
+
+enum VertexKind<'a> {
+    Camera,
+    Light(&'a dyn Light),
+    Surface(HitRecord),
+}
+
+struct Vertex<'a> {
+    kind: VertexKind<'a>,
+    p: Point3,
+    // Zero for vertices that aren't on a surface, whose densities aren't foreshortened.
+    normal: Vec3,
+    // Unit direction towards the previous vertex of the subpath. Zero for the first vertex.
+    to_prev: Vec3,
+    // Throughput of the subpath up to this vertex.
+    beta: Color,
+    // Radiance emitted towards the previous vertex. Only set on camera subpaths.
+    emitted: Color,
+    // Area densities of sampling this vertex from the previous vertex of the subpath, and from
+    // the next one.
+    pdf_fwd: f64,
+    pdf_rev: f64,
+    // Whether the subpath continued from this vertex through a delta lobe.
+    is_delta: bool,
+    // Whether this is a point on a light that no other path can reach, such as a point light.
+    is_delta_light: bool,
+}
+
+impl<'a> Vertex<'a> {
+    fn new(kind: VertexKind<'a>, p: Point3, normal: Vec3, to_prev: Vec3, beta: Color) -> Self {
+        Vertex {
+            kind,
+            p,
+            normal,
+            to_prev,
+            beta,
+            emitted: Color::default(),
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+            is_delta: false,
+            is_delta_light: false,
+        }
+    }
+
+    // The light emitting at this vertex, if there is one.
+    fn light(&self, context: &'a RenderContext) -> Option<&'a dyn Light> {
+        match &self.kind {
+            VertexKind::Light(light) => Some(*light),
+            VertexKind::Surface(record) => context.lights.object_light(record.object),
+            VertexKind::Camera => None,
+        }
+    }
+
+    // The BSDF at this vertex, for light arriving from `next` and leaving towards the previous
+    // vertex, times the absolute cosine towards `next`.
+    fn f(&self, next: &Vertex, wavelength: Option<f64>) -> Color {
+        let VertexKind::Surface(record) = &self.kind else {
+            return Color::default();
+        };
+        let wi = (next.p - self.p).unit_vector();
+        let mat = record.mat.as_ref().unwrap();
+        upsample(mat.eval(&self.to_prev, &wi, record), wavelength)
+    }
+
+    // Area density with which a subpath arriving from `prev` would continue from this vertex
+    // to `next`.
+    fn pdf(&self, context: &'a RenderContext, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        let w = next.p - self.p;
+        let pdf = match &self.kind {
+            VertexKind::Camera => context.camera.pdf_we(&w),
+            VertexKind::Light(_) => return self.pdf_light(context, next),
+            VertexKind::Surface(record) => {
+                let Some(prev) = prev else {
+                    return 0.0;
+                };
+                let wo = (prev.p - self.p).unit_vector();
+                record
+                    .mat
+                    .as_ref()
+                    .unwrap()
+                    .pdf(&wo, &w.unit_vector(), record)
+            }
+        };
+        self.convert_density(pdf, next)
+    }
+
+    // Area density with which a light subpath starting at this vertex would continue to `next`.
+    fn pdf_light(&self, context: &'a RenderContext, next: &Vertex) -> f64 {
+        let Some(light) = self.light(context) else {
+            return 0.0;
+        };
+        let w = (next.p - self.p).unit_vector();
+        let (_, pdf_dir) = light.pdf_le(&self.p, &self.normal, &w);
+        self.convert_density(pdf_dir, next)
+    }
+
+    // Area density with which a light subpath would start at this vertex, towards `next`.
+    fn pdf_light_origin(&self, context: &'a RenderContext, next: &Vertex) -> f64 {
+        let Some(light) = self.light(context) else {
+            return 0.0;
+        };
+        let w = (next.p - self.p).unit_vector();
+        let (pdf_pos, _) = light.pdf_le(&self.p, &self.normal, &w);
+        pdf_pos / context.lights.len() as f64
+    }
+
+    // Converts a solid angle density at this vertex into an area density at `next`.
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        let w = next.p - self.p;
+        let distance_squared = w.length_squared();
+        if distance_squared == 0.0 {
+            return 0.0;
+        }
+
+        let mut pdf = pdf / distance_squared;
+        if !next.normal.is_near_zero() {
+            pdf *= next.normal.dot(&w).abs() / distance_squared.sqrt();
+        }
+        pdf
+    }
+}
+
+impl Integrator for Bdpt {
+    fn radiance(&self, ray: &Ray, context: &RenderContext, wavelength: Option<f64>) -> Color {
+        let one = Color::new(1.0, 1.0, 1.0);
+        let mut camera = Vertex::new(
+            VertexKind::Camera,
+            ray.origin,
+            Vec3::default(),
+            Vec3::default(),
+            one,
+        );
+        // An orthographic camera's rays can't be hit by chance, like a mirror's reflection.
+        camera.is_delta = context.camera.projection() != Projection::Perspective;
+        let mut camera_path = vec![camera];
+        let mut emitters = Vec::new();
+        let mut radiance = random_walk(
+            *ray,
+            one,
+            context.camera.pdf_we(&ray.direction),
+            context,
+            wavelength,
+            &mut camera_path,
+            MAX_DEPTH as usize + 2,
+            Some(&mut emitters),
+        );
+        let light_path = light_subpath(context, wavelength, ray.time);
+
+        // Analytic lights aren't part of the world, so the camera subpath passes through them
+        // instead of ending there. Each one it passed ends a separate path.
+        for (t, emitter) in &emitters {
+            let camera: Vec<_> = camera_path[..*t].iter().chain([emitter]).collect();
+            radiance += emitter.beta * emitter.emitted * mis_weight(context, &camera, &[]);
+        }
+
+        // Paths with a single light vertex sample a fresh one, so they're tried even if the
+        // light subpath is empty.
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len().max(1) {
+                let depth = (s + t) as i32 - 2;
+                if (s == 1 && t == 1) || !(0..=MAX_DEPTH).contains(&depth) {
+                    continue;
+                }
+                radiance += connect(
+                    context,
+                    &camera_path,
+                    &light_path,
+                    s,
+                    t,
+                    wavelength,
+                    ray.time,
+                );
+            }
+        }
+
+        radiance
+    }
+}
+
+// Starts a subpath at a uniformly chosen light, at the same `time` as the camera subpath.
+fn light_subpath<'a>(
+    context: &'a RenderContext,
+    wavelength: Option<f64>,
+    time: f64,
+) -> Vec<Vertex<'a>> {
+    let mut path = Vec::new();
+    let Some((light, probability)) = context.lights.choose() else {
+        return path;
+    };
+    let Some(emission) = light.sample_le(wavelength, time) else {
+        return path;
+    };
+    if emission.pdf_pos == 0.0 || emission.pdf_dir == 0.0 || emission.radiance.is_near_zero() {
+        return path;
+    }
+
+    let pdf_pos = probability * emission.pdf_pos;
+    let mut vertex = Vertex::new(
+        VertexKind::Light(light),
+        emission.ray.origin,
+        emission.normal,
+        Vec3::default(),
+        emission.radiance / pdf_pos,
+    );
+    vertex.pdf_fwd = pdf_pos;
+    vertex.is_delta_light = emission.is_delta;
+    path.push(vertex);
+
+    let direction = emission.ray.direction.unit_vector();
+    let cosine = if emission.normal.is_near_zero() {
+        1.0
+    } else {
+        emission.normal.dot(&direction).abs()
+    };
+    let mut ray = emission.ray;
+    ray.wavelength = wavelength;
+    random_walk(
+        ray,
+        emission.radiance * (cosine / (pdf_pos * emission.pdf_dir)),
+        emission.pdf_dir,
+        context,
+        wavelength,
+        &mut path,
+        MAX_DEPTH as usize + 1,
+        None,
+    );
+    path
+}
+
+// Extends `path` by following BSDF samples from `ray`, which leaves the path's last vertex in a
+// direction picked with solid angle density `pdf_dir`. Camera subpaths pass `emitters`, which
+// collects the analytic lights passed on the way along with the number of vertices before
+// them; their walk returns the sky seen if the path escapes.
+#[allow(clippy::too_many_arguments)]
+fn random_walk<'a>(
+    mut ray: Ray,
+    mut beta: Color,
+    pdf_dir: f64,
+    context: &'a RenderContext,
+    wavelength: Option<f64>,
+    path: &mut Vec<Vertex<'a>>,
+    max_vertices: usize,
+    mut emitters: Option<&mut Vec<(usize, Vertex<'a>)>>,
+) -> Color {
+    let scene = context.scene;
+    let mut pdf_fwd = pdf_dir;
+
+    while path.len() < max_vertices {
+        let hit = scene.hit(&ray, 0.001..=f64::INFINITY);
+        let prev = path.last().unwrap();
+        let to_prev = -ray.direction.unit_vector();
+
+        if let Some(emitters) = emitters.as_deref_mut() {
+            let t_max = hit.as_ref().map_or(f64::INFINITY, |record| record.t);
+            for light in context.lights.analytic() {
+                let Some((t, normal)) = light.hit_surface(&ray, 0.001..=t_max) else {
+                    continue;
+                };
+                let mut vertex = Vertex::new(
+                    VertexKind::Light(light.as_ref()),
+                    ray.at(t),
+                    normal,
+                    to_prev,
+                    beta,
+                );
+                vertex.emitted = light
+                    .hit_le(&ray, 0.001..=t_max, wavelength)
+                    .unwrap_or_default();
+                vertex.pdf_fwd = prev.convert_density(pdf_fwd, &vertex);
+                emitters.push((path.len(), vertex));
+            }
+        }
+
+        let Some(record) = hit else {
+            if emitters.is_some() {
+                return beta * upsample(scene.background(&ray), wavelength);
+            }
+            break;
+        };
+
+        let mat = record.mat.clone().unwrap();
+        let sample = mat.sample(&ray, &record);
+        // Density of sampling the previous vertex from this one, for paths built the other way.
+        let pdf_rev = match &sample {
+            Some(sample) if !sample.is_delta => {
+                mat.pdf(&sample.ray.direction.unit_vector(), &to_prev, &record)
+            }
+            _ => 0.0,
+        };
+
+        let mut vertex = Vertex::new(
+            VertexKind::Surface(record.clone()),
+            record.p,
+            record.normal,
+            to_prev,
+            beta,
+        );
+        if emitters.is_some() {
+            vertex.emitted = emitted_radiance(mat.as_ref(), &ray, &record, wavelength);
+        }
+        vertex.pdf_fwd = prev.convert_density(pdf_fwd, &vertex);
+        vertex.is_delta = sample.as_ref().is_some_and(|sample| sample.is_delta);
+        path.push(vertex);
+
+        let n = path.len();
+        let Some(sample) = sample else {
+            break;
+        };
+        if n >= max_vertices {
+            break;
+        }
+        path[n - 2].pdf_rev = path[n - 1].convert_density(pdf_rev, &path[n - 2]);
+
+        let Some(survivor) =
+            russian_roulette(beta * upsample(sample.weight, wavelength), n as i32 - 1)
+        else {
+            break;
+        };
+        beta = survivor;
+        pdf_fwd = if sample.is_delta { 0.0 } else { sample.pdf };
+        ray = sample.ray;
+    }
+
+    Color::default()
+}
+
+// Contribution of the path made of the first `t` vertices of the camera subpath and the first
+// `s` vertices of the light subpath, weighted against the other ways of sampling it.
+fn connect(
+    context: &RenderContext,
+    camera: &[Vertex],
+    light: &[Vertex],
+    s: usize,
+    t: usize,
+    wavelength: Option<f64>,
+    time: f64,
+) -> Color {
+    let scene = context.scene;
+    let pt = &camera[t - 1];
+
+    if s == 0 {
+        // The camera subpath found a light by itself.
+        if pt.emitted.is_near_zero() {
+            return Color::default();
+        }
+        let camera: Vec<_> = camera[..t].iter().collect();
+        return pt.beta * pt.emitted * mis_weight(context, &camera, &[]);
+    }
+
+    if t == 1 {
+        // Connect the light subpath straight to the camera, which sees it through some pixel.
+        let qs = &light[s - 1];
+        let Some(sample) = context.camera.sample_wi(&qs.p) else {
+            return Color::default();
+        };
+        let mut vertex = Vertex::new(
+            VertexKind::Camera,
+            qs.p + sample.wi * sample.distance,
+            Vec3::default(),
+            Vec3::default(),
+            Color::new(sample.weight, sample.weight, sample.weight),
+        );
+        vertex.pdf_fwd = 1.0;
+
+        let contribution = qs.beta * qs.f(&vertex, wavelength) * vertex.beta;
+        let shadow_ray = Ray {
+            time,
+            ..Ray::new(qs.p, sample.wi)
+        };
+        if contribution.is_near_zero() {
+            return Color::default();
+        }
+        let contribution =
+            contribution * scene.transmittance(&shadow_ray, 0.001..=sample.distance - 0.001);
+        if contribution.is_near_zero() {
+            return Color::default();
+        }
+
+        let light: Vec<_> = light[..s].iter().collect();
+        let weight = mis_weight(context, &[&vertex], &light);
+        let (i, j) = sample.pixel;
+        context
+            .splats
+            .splat(i, j, contribution * weight, wavelength);
+        return Color::default();
+    }
+
+    if s == 1 {
+        // Sample a fresh point on a light, as next-event estimation does.
+        let Some((light, probability)) = context.lights.choose() else {
+            return Color::default();
+        };
+        let Some(sample) = light.sample_li(&pt.p, wavelength, time) else {
+            return Color::default();
+        };
+        if sample.pdf == 0.0 {
+            return Color::default();
+        }
+
+        // Distant lights get a stand-in point one unit away, as only its direction matters.
+        let distance = if sample.distance.is_finite() {
+            sample.distance
+        } else {
+            1.0
+        };
+        let mut vertex = Vertex::new(
+            VertexKind::Light(light),
+            pt.p + sample.wi * distance,
+            sample.normal,
+            Vec3::default(),
+            sample.radiance / (sample.pdf * probability),
+        );
+        vertex.pdf_fwd = vertex.pdf_light_origin(context, pt);
+        vertex.is_delta_light = sample.is_delta || !sample.distance.is_finite();
+
+        let contribution = pt.beta * pt.f(&vertex, wavelength) * vertex.beta;
+        let shadow_ray = Ray {
+            time,
+            ..Ray::new(pt.p, sample.wi)
+        };
+        if contribution.is_near_zero() {
+            return Color::default();
+        }
+        let contribution =
+            contribution * scene.transmittance(&shadow_ray, 0.001..=sample.distance - 0.001);
+        if contribution.is_near_zero() {
+            return Color::default();
+        }
+
+        let camera: Vec<_> = camera[..t].iter().collect();
+        return contribution * mis_weight(context, &camera, &[&vertex]);
+    }
+
+    let qs = &light[s - 1];
+    let to_qs = qs.p - pt.p;
+    let distance = to_qs.length();
+    let contribution =
+        qs.beta * qs.f(pt, wavelength) * pt.f(qs, wavelength) * pt.beta / (distance * distance);
+    let shadow_ray = Ray {
+        time,
+        ..Ray::new(pt.p, to_qs / distance)
+    };
+    if contribution.is_near_zero() {
+        return Color::default();
+    }
+    let contribution = contribution * scene.transmittance(&shadow_ray, 0.001..=distance - 0.001);
+    if contribution.is_near_zero() {
+        return Color::default();
+    }
+
+    let camera: Vec<_> = camera[..t].iter().collect();
+    let light: Vec<_> = light[..s].iter().collect();
+    contribution * mis_weight(context, &camera, &light)
+}
+
+// Power heuristic weight of the path made by connecting the last vertices of `camera` and
+// `light`, against all the other numbers of vertices the two subpaths could have contributed.
+fn mis_weight(context: &RenderContext, camera: &[&Vertex], light: &[&Vertex]) -> f64 {
+    let (s, t) = (light.len(), camera.len());
+    if s + t == 2 {
+        return 1.0;
+    }
+
+    // (pdf_fwd, pdf_rev, is_delta) of each vertex, as they'd be had the path been sampled
+    // as a whole.
+    let mut camera_pdfs: Vec<_> = camera
+        .iter()
+        .map(|vertex| (vertex.pdf_fwd, vertex.pdf_rev, vertex.is_delta))
+        .collect();
+    let mut light_pdfs: Vec<_> = light
+        .iter()
+        .map(|vertex| (vertex.pdf_fwd, vertex.pdf_rev, vertex.is_delta))
+        .collect();
+
+    let pt = camera[t - 1];
+    let pt_minus = (t > 1).then(|| camera[t - 2]);
+    let qs = (s > 0).then(|| light[s - 1]);
+    let qs_minus = (s > 1).then(|| light[s - 2]);
+
+    // The connected vertices can't be delta, or the connection would have been worth nothing.
+    camera_pdfs[t - 1].2 = false;
+    camera_pdfs[t - 1].1 = match (qs, pt_minus) {
+        (Some(qs), _) => qs.pdf(context, qs_minus, pt),
+        (None, Some(pt_minus)) => pt.pdf_light_origin(context, pt_minus),
+        (None, None) => 0.0,
+    };
+    if let Some(pt_minus) = pt_minus {
+        camera_pdfs[t - 2].1 = match qs {
+            Some(qs) => pt.pdf(context, Some(qs), pt_minus),
+            None => pt.pdf_light(context, pt_minus),
+        };
+    }
+    if let Some(qs) = qs {
+        light_pdfs[s - 1].2 = false;
+        light_pdfs[s - 1].1 = pt.pdf(context, pt_minus, qs);
+        if let Some(qs_minus) = qs_minus {
+            light_pdfs[s - 2].1 = qs.pdf(context, Some(pt), qs_minus);
+        }
+    }
+
+    // Delta vertices have no density, and leave the ratios unchanged.
+    let remap = |pdf: f64| if pdf == 0.0 { 1.0 } else { pdf };
+
+    // Walk each subpath back from the connection, working out how much more likely each
+    // other strategy is to sample the path than this one.
+    let mut sum = 0.0;
+    let mut ratio = 1.0;
+    for i in (1..t).rev() {
+        ratio *= remap(camera_pdfs[i].1) / remap(camera_pdfs[i].0);
+        if !camera_pdfs[i].2 && !camera_pdfs[i - 1].2 {
+            sum += ratio * ratio;
+        }
+    }
+
+    ratio = 1.0;
+    for i in (0..s).rev() {
+        ratio *= remap(light_pdfs[i].1) / remap(light_pdfs[i].0);
+        let delta_light = if i > 0 {
+            light_pdfs[i - 1].2
+        } else {
+            light[0].is_delta_light
+        };
+        if !light_pdfs[i].2 && !delta_light {
+            sum += ratio * ratio;
+        }
+    }
+
+    1.0 / (1.0 + sum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::hittable::{HittableList, Sphere, Triangle};
+    use crate::integrator::PathTracer;
+    use crate::material::{DiffuseLight, Lambertian, Material};
+    use crate::scene::Scene;
+    use crate::spectrum::Spectrum;
+    use std::sync::Arc;
+
+    #[test]
+    fn converges_to_the_path_tracer() {
+        // A diffuse room and ball, lit by a quad light in view that faces the floor.
+        let mut world = HittableList::new();
+        let white: Arc<dyn Material> = Arc::new(Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5),
+        });
+        let light: Arc<dyn Material> = Arc::new(DiffuseLight::new(Spectrum::Constant(8.0)));
+        world.push(Box::new(Sphere::new(Point3::default(), 3.0, white.clone())));
+        world.push(Box::new(Sphere::new(
+            Point3::new(0.3, -0.6, -1.8),
+            0.4,
+            white,
+        )));
+        let (corner, u, v) = (
+            Point3::new(-0.5, 1.0, -2.5),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        );
+        world.push(Box::new(Triangle::new(
+            corner,
+            corner + u,
+            corner + u + v,
+            light.clone(),
+        )));
+        world.push(Box::new(Triangle::new(
+            corner,
+            corner + u + v,
+            corner + v,
+            light,
+        )));
+        let scene = Scene::new(world);
+
+        // Compared region by region, so light picked up by the wrong strategies shows.
+        let size = 24;
+        let camera = Camera::new(1.0, size as u32);
+        let regions = |pixels: Vec<Color>| -> Vec<f64> {
+            (0..4)
+                .map(|region| {
+                    let quarter = pixels.iter().enumerate().filter(|(index, _)| {
+                        let (i, j) = (index % size, index / size);
+                        2 * i / size + 2 * (2 * j / size) == region
+                    });
+                    let sum: f64 = quarter.map(|(_, pixel)| pixel.luminance()).sum();
+                    sum / (size * size / 4) as f64
+                })
+                .collect()
+        };
+        let expected = regions(camera.render_pixels(&scene, &PathTracer));
+        let bdpt = regions(camera.render_pixels(&scene, &Bdpt));
+        for (region, (bdpt, expected)) in bdpt.iter().zip(&expected).enumerate() {
+            assert!(
+                (bdpt / expected - 1.0).abs() < 0.1,
+                "Region {region}: {bdpt} vs {expected}"
+            );
+        }
+    }
+}
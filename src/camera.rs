@@ -1,109 +1,361 @@
 use image::{ImageBuffer, RgbImage};
-use rayon::prelude::*;
-
 use std::cmp::max;
 
+use crate::aabb::Aabb;
 use crate::color::Color;
-use crate::hittable::{Hittable, HittableList};
+use crate::film::Film;
+use crate::integrator::{Integrator, RenderContext};
+use crate::light::LightList;
 use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::spectrum::sample_wavelength;
 use crate::utils::get_random_f64;
 use crate::vec::{Point3, Vec3};
 
-const SAMPLES_PER_PIXEL: i32 = 100;
-const MAX_DEPTH: i32 = 50;
+pub const SAMPLES_PER_PIXEL: i32 = 100;
+
+// A connection from a point in the scene to the camera, for light tracing.
+pub struct CameraSample {
+    // The pixel the point is seen through.
+    pub pixel: (u32, u32),
+    // Unit direction from the point towards the camera.
+    pub wi: Vec3,
+    pub distance: f64,
+    // The camera's importance along the connection, divided by the density of `wi`.
+    pub weight: f64,
+}
+
+// How the camera maps the scene onto the image.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Projection {
+    // Rays spread out from the camera's position through the viewport, so further objects look
+    // smaller.
+    Perspective,
+    // Rays leave every point of a viewport `height` high in parallel, along the line of sight,
+    // so objects look the same size at any distance. Only what's in front of the camera is
+    // seen.
+    Orthographic { height: f64 },
+}
 
+#[derive(Clone)]
 pub struct Camera {
     image_width: u32,
     image_height: u32,
+    // Where the camera is, the point it looks at, the direction that's up in the image, and
+    // the vertical field of view in degrees.
+    look_from: Point3,
+    look_at: Point3,
+    vup: Vec3,
+    vfov: f64,
+    projection: Projection,
     center: Point3,
     pixel00_loc: Point3,
     pixel_delta_u: Vec3,
     pixel_delta_v: Vec3,
+    spectral: bool,
+    // Times the shutter opens and closes.
+    shutter: (f64, f64),
 }
 
 impl Camera {
+    // A camera at the origin looking down -z, with a vertical field of view of 90 degrees.
     pub fn new(aspect_ratio: f64, image_width: u32) -> Camera {
         // Calculate the image height, and ensure that it's at least 1.
         let image_height: u32 = max(1, (image_width as f64 / aspect_ratio) as u32);
 
-        // Camera
-        let focal_length: f64 = 1.0;
-        let viewport_height: f64 = 2.0;
-        let viewport_width: f64 = viewport_height * (image_width as f64 / image_height as f64);
-        let camera_center = Point3::default();
+        let mut camera = Camera {
+            image_width,
+            image_height,
+            look_from: Point3::default(),
+            look_at: Point3::new(0.0, 0.0, -1.0),
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            vfov: 90.0,
+            projection: Projection::Perspective,
+            center: Point3::default(),
+            pixel00_loc: Point3::default(),
+            pixel_delta_u: Vec3::default(),
+            pixel_delta_v: Vec3::default(),
+            spectral: false,
+            shutter: (0.0, 0.0),
+        };
+        camera.place();
+        camera
+    }
+
+    // Moves the camera to `look_from`, facing `look_at`, with `vup` pointing up in the image.
+    pub fn with_look_at(mut self, look_from: Point3, look_at: Point3, vup: Vec3) -> Camera {
+        self.look_from = look_from;
+        self.look_at = look_at;
+        self.vup = vup;
+        self.place();
+        self
+    }
+
+    pub fn with_projection(mut self, projection: Projection) -> Camera {
+        self.projection = projection;
+        self.place();
+        self
+    }
+
+    // Sets the vertical field of view, in degrees. Orthographic cameras ignore it.
+    pub fn with_vfov(mut self, vfov: f64) -> Camera {
+        self.vfov = vfov;
+        self.place();
+        self
+    }
+
+    // Circles the camera around the center of `bounds`, `azimuth` degrees around the y axis
+    // from +z and `elevation` degrees above the horizon, just far enough away to keep all of
+    // `bounds` in view.
+    pub fn with_orbit(self, bounds: &Aabb, azimuth: f64, elevation: f64) -> Camera {
+        let (azimuth, elevation) = (azimuth.to_radians(), elevation.to_radians());
+        let direction = Vec3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            elevation.cos() * azimuth.cos(),
+        );
+        let center = bounds.center();
+        let distance = self.framing_distance(bounds);
+        let camera = self.with_look_at(
+            center + direction * distance,
+            center,
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+
+        match camera.projection {
+            Projection::Perspective => camera,
+            Projection::Orthographic { .. } => {
+                // Fit the sphere around the box in the narrower side of the viewport.
+                let diameter = bounds.diagonal().length();
+                let aspect_ratio = camera.image_width as f64 / camera.image_height as f64;
+                camera.with_projection(Projection::Orthographic {
+                    height: diameter * aspect_ratio.recip().max(1.0),
+                })
+            }
+        }
+    }
+
+    // Distance from the center of `bounds` at which the sphere around them just fits in the
+    // narrower of the two fields of view, whichever way the camera faces.
+    fn framing_distance(&self, bounds: &Aabb) -> f64 {
+        let radius = bounds.diagonal().length() / 2.0;
+        let half_vfov = self.vfov.to_radians() / 2.0;
+        let half_hfov =
+            (half_vfov.tan() * self.image_width as f64 / self.image_height as f64).atan();
+        radius / half_vfov.min(half_hfov).sin()
+    }
+
+    // Aims the camera at the center of `bounds`, and moves it along its line of sight until the
+    // box just fills the frame. Pass the world's bounding box to frame the whole scene, or an
+    // object's to frame that.
+    pub fn with_framing(self, bounds: &Aabb) -> Camera {
+        let (u, v, w) = self.basis();
+        let center = bounds.center();
+        let tan_half_vfov = (self.vfov.to_radians() / 2.0).tan();
+        let tan_half_hfov = tan_half_vfov * self.image_width as f64 / self.image_height as f64;
+
+        let aspect_ratio = self.image_width as f64 / self.image_height as f64;
+
+        // Each corner of the box is in view once the camera is far enough back for it to fall
+        // within both fields of view, seen from its own depth. An orthographic viewport has to
+        // reach each corner instead, with the camera anywhere in front of the box.
+        let mut distance: f64 = 0.0;
+        let mut depth_in_front: f64 = 0.0;
+        let mut height: f64 = 0.0;
+        for corner in bounds.corners() {
+            let p = corner - center;
+            let depth = p.dot(&w);
+            distance = distance
+                .max(depth + p.dot(&u).abs() / tan_half_hfov)
+                .max(depth + p.dot(&v).abs() / tan_half_vfov);
+            depth_in_front = depth_in_front.max(depth);
+            height = height
+                .max(2.0 * p.dot(&v).abs())
+                .max(2.0 * p.dot(&u).abs() / aspect_ratio);
+        }
+
+        let vup = self.vup;
+        match self.projection {
+            Projection::Perspective => self.with_look_at(center + w * distance, center, vup),
+            Projection::Orthographic { .. } => {
+                // Back off by the size of the box, so none of it is right at the camera.
+                let distance = depth_in_front + bounds.diagonal().length();
+                self.with_look_at(center + w * distance, center, vup)
+                    .with_projection(Projection::Orthographic { height })
+            }
+        }
+    }
+
+    // Orthonormal basis of the camera: `u` to the right, `v` up and `w` backwards.
+    fn basis(&self) -> (Vec3, Vec3, Vec3) {
+        let w = (self.look_from - self.look_at).unit_vector();
+        let u = self.vup.cross(&w).unit_vector();
+        let v = w.cross(&u);
+        (u, v, w)
+    }
+
+    // Lays out the viewport from the camera's position, orientation and field of view.
+    fn place(&mut self) {
+        let camera_center = self.look_from;
+        let focal_length = (self.look_from - self.look_at).length();
+        let viewport_height = match self.projection {
+            Projection::Perspective => 2.0 * (self.vfov.to_radians() / 2.0).tan() * focal_length,
+            Projection::Orthographic { height } => height,
+        };
+        let viewport_width = viewport_height * (self.image_width as f64 / self.image_height as f64);
+
+        let (u, v, w) = self.basis();
 
         // Calculate the vectors across the horizontal and down the vertical viewport edges.
-        let viewport_u = Vec3::new(viewport_width, 0.0, 0.0);
-        let viewport_v = Vec3::new(0.0, -viewport_height, 0.0);
+        let viewport_u = u * viewport_width;
+        let viewport_v = -v * viewport_height;
 
-        let pixel_delta_u = viewport_u / image_width as f64;
-        let pixel_delta_v = viewport_v / image_height as f64;
+        self.pixel_delta_u = viewport_u / self.image_width as f64;
+        self.pixel_delta_v = viewport_v / self.image_height as f64;
 
         // This positions the viewport such that a vector can be constructed that passes through
         // `camera_center`, is orthogonal to the viewport, and passes through the center of the viewport
-        let viewport_upper_left = camera_center
-            - Point3::new(0.0, 0.0, focal_length)
-            - viewport_u / 2.0
-            - viewport_v / 2.0;
+        let viewport_upper_left =
+            camera_center - w * focal_length - viewport_u / 2.0 - viewport_v / 2.0;
 
         // This is the top left pixel, each subsequent pixel will be calculated by adding a linear combination of
         // `pixel_delta_u` and `pixel_delta_v` to this point
-        let pixel00_loc = viewport_upper_left + (pixel_delta_u + pixel_delta_v) * 0.5;
+        self.pixel00_loc = viewport_upper_left + (self.pixel_delta_u + self.pixel_delta_v) * 0.5;
+        self.center = camera_center;
+    }
 
-        Camera {
-            image_width,
-            image_height,
-            center: camera_center,
-            pixel00_loc,
-            pixel_delta_u,
-            pixel_delta_v,
+    // Switches to the spectral pipeline: every camera sample follows a single wavelength,
+    // and the film accumulates its contribution through the CIE colour matching functions.
+    pub fn with_spectral(mut self, spectral: bool) -> Camera {
+        self.spectral = spectral;
+        self
+    }
+
+    // Keeps the shutter open from `open` to `close`, so objects moving in between are blurred
+    // along their motion. Times are the ones moving objects are keyframed at.
+    pub fn with_shutter(mut self, open: f64, close: f64) -> Camera {
+        self.shutter = (open, close);
+        self
+    }
+
+    pub fn render(&self, scene: &Scene, integrator: &dyn Integrator) {
+        self.render_to(scene, integrator, "image.png");
+    }
+
+    // Renders the image and saves it as `filename`.
+    pub fn render_to(&self, scene: &Scene, integrator: &dyn Integrator, filename: &str) {
+        let mut img: RgbImage = ImageBuffer::new(self.image_width, self.image_height);
+        for (index, color) in self
+            .render_pixels(scene, integrator)
+            .into_iter()
+            .enumerate()
+        {
+            let (i, j) = (
+                index as u32 % self.image_width,
+                index as u32 / self.image_width,
+            );
+            *img.get_pixel_mut(i, j) = color.get_rgb(SAMPLES_PER_PIXEL);
         }
+
+        img.save(filename).unwrap();
+        println!("All done!");
     }
 
-    pub fn render(&self, world: &HittableList) {
-        // Render
-        let mut img: RgbImage = ImageBuffer::new(self.image_width as u32, self.image_height as u32);
-
-        // Collect computed pixel colors in a Vec
-        let pixel_colors: Vec<_> = (0..self.image_height)
-            .into_par_iter()
-            .flat_map(|j| {
-                (0..self.image_width).into_par_iter().map(move |i| {
-                    let mut pixel_color = Color::default();
-                    for _ in 0..SAMPLES_PER_PIXEL {
-                        let ray = self.get_ray(i, j);
-                        pixel_color += Self::ray_color(&ray, world, MAX_DEPTH);
-                    }
-                    (i, j, pixel_color.get_rgb(SAMPLES_PER_PIXEL))
-                })
+    // Renders the image, row by row, as the sum of the `SAMPLES_PER_PIXEL` samples of each
+    // pixel.
+    pub fn render_pixels(&self, scene: &Scene, integrator: &dyn Integrator) -> Vec<Color> {
+        let context = RenderContext {
+            scene,
+            lights: LightList::new(scene),
+            camera: self,
+            splats: Film::new(self.image_width, self.image_height),
+        };
+
+        // The computed colors, and the light splatted onto them
+        let pixel_colors = integrator.render(&context);
+        pixel_colors
+            .into_iter()
+            .enumerate()
+            .map(|(index, pixel_color)| {
+                let (i, j) = (
+                    index as u32 % self.image_width,
+                    index as u32 / self.image_width,
+                );
+                pixel_color + context.splats.get(i, j)
             })
-            .collect();
+            .collect()
+    }
+
+    pub fn resolution(&self) -> (u32, u32) {
+        (self.image_width, self.image_height)
+    }
 
-        // Update the image buffer with the computed colors
-        for (i, j, pixel) in pixel_colors {
-            *img.get_pixel_mut(i, j) = pixel;
+    // A randomly sampled camera ray through the pixel at location i,j. In spectral mode it
+    // also follows a randomly sampled wavelength.
+    pub fn sample_ray(&self, i: u32, j: u32) -> Ray {
+        let mut ray = self.get_ray(i, j);
+        if self.spectral {
+            ray.wavelength = Some(sample_wavelength());
         }
+        ray
+    }
 
-        img.save("image.png").unwrap();
-        println!("All done!");
+    // A random time while the shutter is open.
+    pub fn sample_time(&self) -> f64 {
+        let (open, close) = self.shutter;
+        if open == close {
+            return open;
+        }
+        open + (close - open) * get_random_f64()
+    }
+
+    pub fn shutter(&self) -> (f64, f64) {
+        self.shutter
     }
 
-    fn ray_color(ray: &Ray, world: &HittableList, depth: i32) -> Color {
-        if depth <= 0 {
-            return Color::new(0.0, 0.0, 0.0);
+    pub fn is_spectral(&self) -> bool {
+        self.spectral
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    // Solid angle density with which `get_ray` picks `direction`. Orthographic cameras pick a
+    // single direction, which has no density.
+    pub fn pdf_we(&self, direction: &Vec3) -> f64 {
+        if self.projection != Projection::Perspective || self.raster(direction).is_none() {
+            return 0.0;
         }
 
-        if let Some(record) = world.hit(ray, 0.001..=f64::INFINITY) {
-            if let Some((attenuation, scattered)) =
-                (*record.mat.clone().unwrap()).scatter(ray, record)
-            {
-                return attenuation * Self::ray_color(&scattered, world, depth - 1);
-            }
-            return Color::default();
+        let (axis, focal_length) = self.view_axis();
+        let cos_theta = direction.unit_vector().dot(&axis);
+        focal_length * focal_length / (self.viewport_area() * cos_theta.powi(3))
+    }
+
+    // Connects `p` to the camera, if it's in view. Orthographic cameras can't be connected to,
+    // as only rays along their line of sight reach them.
+    pub fn sample_wi(&self, p: &Point3) -> Option<CameraSample> {
+        if self.projection != Projection::Perspective {
+            return None;
         }
+        let to_camera = self.center - *p;
+        let (x, y) = self.raster(&-to_camera)?;
+
+        let distance = to_camera.length();
+        let wi = to_camera / distance;
+        let (axis, focal_length) = self.view_axis();
+        let cos_theta = -wi.dot(&axis);
 
-        let a = 0.5 * (ray.direction.unit_vector().y + 1.0);
-        Color::new(1.0, 1.0, 1.0) * (1.0 - a) + Color::new(0.5, 0.7, 1.0) * a
+        // The importance is focal_length^2 / (area * cos^4), and the density of `wi` is
+        // distance^2 / cos.
+        Some(CameraSample {
+            pixel: (x as u32, y as u32),
+            wi,
+            distance,
+            weight: focal_length * focal_length
+                / (self.viewport_area() * cos_theta.powi(3) * distance * distance),
+        })
     }
 
     fn get_ray(&self, i: u32, j: u32) -> Ray {
@@ -112,11 +364,57 @@ impl Camera {
             self.pixel00_loc + self.pixel_delta_u * i as f64 + self.pixel_delta_v * j as f64;
         let pixel_sample = pixel_center + self.pixel_sample_square();
 
-        let ray_origin = self.center;
+        let (ray_origin, ray_direction) = match self.projection {
+            Projection::Perspective => (self.center, pixel_sample - self.center),
+            // Start from the camera's plane, straight behind the pixel.
+            Projection::Orthographic { .. } => {
+                let axis = self.look_at - self.look_from;
+                (pixel_sample - axis, axis)
+            }
+        };
+
+        Ray {
+            time: self.sample_time(),
+            ..Ray::new(ray_origin, ray_direction)
+        }
+    }
 
-        let ray_direction = pixel_sample - ray_origin;
+    // Unit vector from the camera center towards the center of the viewport, and the distance
+    // between the two.
+    fn view_axis(&self) -> (Vec3, f64) {
+        let viewport_center = self.pixel00_loc
+            + (self.pixel_delta_u * (self.image_width - 1) as f64
+                + self.pixel_delta_v * (self.image_height - 1) as f64)
+                * 0.5;
+        let axis = viewport_center - self.center;
+        (axis.unit_vector(), axis.length())
+    }
 
-        Ray::new(ray_origin, ray_direction)
+    fn viewport_area(&self) -> f64 {
+        self.pixel_delta_u.length()
+            * self.image_width as f64
+            * self.pixel_delta_v.length()
+            * self.image_height as f64
+    }
+
+    // Where a ray leaving the camera center in `direction` crosses the viewport, in pixels
+    // from its upper left corner.
+    fn raster(&self, direction: &Vec3) -> Option<(f64, f64)> {
+        let (axis, focal_length) = self.view_axis();
+        let along_axis = direction.dot(&axis);
+        if along_axis <= 0.0 {
+            return None;
+        }
+
+        let viewport_upper_left =
+            self.pixel00_loc - (self.pixel_delta_u + self.pixel_delta_v) * 0.5;
+        let offset = self.center + *direction * (focal_length / along_axis) - viewport_upper_left;
+        let x = offset.dot(&self.pixel_delta_u) / self.pixel_delta_u.length_squared();
+        let y = offset.dot(&self.pixel_delta_v) / self.pixel_delta_v.length_squared();
+
+        let in_view = (0.0..self.image_width as f64).contains(&x)
+            && (0.0..self.image_height as f64).contains(&y);
+        in_view.then_some((x, y))
     }
 
     fn pixel_sample_square(&self) -> Vec3 {
@@ -126,3 +424,35 @@ impl Camera {
         self.pixel_delta_u * px + self.pixel_delta_v * py
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn framing_keeps_every_corner_just_in_view() {
+        let bounds = Aabb::new(Point3::new(3.0, -1.0, -2.0), Point3::new(7.0, 0.5, 4.0));
+        let cam = Camera::new(16.0 / 9.0, 400)
+            .with_look_at(
+                Point3::new(1.0, 0.5, 1.0),
+                Point3::default(),
+                Vec3::new(0.0, 1.0, 0.0),
+            )
+            .with_framing(&bounds.pad(1e-9));
+
+        // Every corner lands on the image, and the closest to an edge is within a pixel of it.
+        // The tight fit puts corners right on the edge, so a hair of padding keeps them inside.
+        let (width, height) = (cam.image_width as f64, cam.image_height as f64);
+        let margin = bounds
+            .corners()
+            .iter()
+            .map(|corner| {
+                let (x, y) = cam
+                    .raster(&(*corner - cam.center))
+                    .expect("Corner out of view");
+                x.min(width - x).min(y).min(height - y)
+            })
+            .fold(f64::INFINITY, f64::min);
+        assert!(margin < 1.0, "Framing left a margin of {margin} pixels");
+    }
+}
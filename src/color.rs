@@ -20,6 +20,10 @@ impl Color {
         ])
     }
 
+    pub fn luminance(self) -> f64 {
+        0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
+    }
+
     pub fn get_color(r: f32, g: f32, b: f32) -> Color {
         Color::new(
             gamma_to_linear(r as f64 / 256.0),
@@ -0,0 +1,48 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::color::Color;
+use crate::spectrum::film_rgb;
+
+// An image that any thread can add light to, such as the contributions light tracing splats
+// onto pixels other than the one being sampled.
+pub struct Film {
+    width: u32,
+    pixels: Vec<[AtomicU64; 3]>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Film {
+        Film {
+            width,
+            pixels: (0..width * height)
+                .map(|_| [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)])
+                .collect(),
+        }
+    }
+
+    // Adds `radiance` to the pixel at location i,j. In spectral mode `radiance` is a grey
+    // holding the radiance at `wavelength`.
+    pub fn splat(&self, i: u32, j: u32, radiance: Color, wavelength: Option<f64>) {
+        let color = film_rgb(radiance, wavelength);
+
+        let pixel = &self.pixels[(j * self.width + i) as usize];
+        for (channel, value) in pixel.iter().zip([color.x, color.y, color.z]) {
+            // There's no atomic float addition, so swap in the sum until no other thread
+            // got in first.
+            channel
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                    Some((f64::from_bits(bits) + value).to_bits())
+                })
+                .unwrap();
+        }
+    }
+
+    pub fn get(&self, i: u32, j: u32) -> Color {
+        let [r, g, b] = &self.pixels[(j * self.width + i) as usize];
+        Color::new(
+            f64::from_bits(r.load(Ordering::Relaxed)),
+            f64::from_bits(g.load(Ordering::Relaxed)),
+            f64::from_bits(b.load(Ordering::Relaxed)),
+        )
+    }
+}
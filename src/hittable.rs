@@ -1,16 +1,34 @@
+#[cfg(feature = "intersection-stats")]
+use std::cell::Cell;
 use std::ops::RangeInclusive;
 use std::sync::Arc;
 
+use cgmath::{Deg, InnerSpace, One, Quaternion, Rotation, Rotation3, Vector3};
+
+use crate::aabb::Aabb;
+use crate::animation::{Keyframes, Lerp};
 use crate::material::Material;
+use crate::onb::Onb;
 use crate::ray::Ray;
+use crate::utils::{get_random_f64, PI};
 use crate::vec::{Point3, Vec3};
 
+#[derive(Clone)]
 pub struct HitRecord {
     pub p: Point3,
     pub normal: Vec3,
     pub mat: Option<Arc<dyn Material>>,
-    t: f64,
+    pub t: f64,
     pub front_face: bool,
+    // Surface coordinates of the hit. For triangles, their interpolated texture coordinates if
+    // they have any, and barycentric coordinates otherwise.
+    pub u: f64,
+    pub v: f64,
+    // Unit direction of increasing `u` along the surface, which anisotropic materials stretch
+    // their highlights along. Zero where the surface doesn't have one.
+    pub tangent: Vec3,
+    // Index of the hit object in the outermost list that was tested.
+    pub object: usize,
 }
 
 impl HitRecord {
@@ -35,33 +53,169 @@ impl Default for HitRecord {
             mat: None,
             t: 0.0,
             front_face: false,
+            u: 0.0,
+            v: 0.0,
+            tangent: Vec3::default(),
+            object: 0,
         }
     }
 }
 
+// Intersection tests are only counted with the `intersection-stats` feature, for
+// `DebugView::Cost`, so that other renders don't pay for the bookkeeping.
+#[cfg(feature = "intersection-stats")]
+thread_local! {
+    // Number of (list nodes, primitives) tested for intersection on this thread.
+    static INTERSECTION_TESTS: Cell<(u32, u32)> = const { Cell::new((0, 0)) };
+}
+
+// Returns the number of list nodes and primitives tested since the last call, and resets both.
+// Always zero without the `intersection-stats` feature.
+#[cfg(feature = "intersection-stats")]
+pub fn take_intersection_tests() -> (u32, u32) {
+    INTERSECTION_TESTS.with(|tests| tests.take())
+}
+
+#[cfg(not(feature = "intersection-stats"))]
+pub fn take_intersection_tests() -> (u32, u32) {
+    (0, 0)
+}
+
+#[cfg(feature = "intersection-stats")]
+fn count_node_test() {
+    INTERSECTION_TESTS.with(|tests| {
+        let (nodes, primitives) = tests.get();
+        tests.set((nodes + 1, primitives));
+    });
+}
+
+#[cfg(feature = "intersection-stats")]
+fn count_primitive_test() {
+    INTERSECTION_TESTS.with(|tests| {
+        let (nodes, primitives) = tests.get();
+        tests.set((nodes, primitives + 1));
+    });
+}
+
+#[cfg(not(feature = "intersection-stats"))]
+fn count_node_test() {}
+
+#[cfg(not(feature = "intersection-stats"))]
+fn count_primitive_test() {}
+
 pub trait Hittable: Sync {
     fn hit(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> Option<HitRecord>;
+
+    // Whether anything lies along `ray` within `ray_t`. Unlike `hit`, this may stop at the first
+    // intersection found and doesn't build a hit record, which makes it the cheaper choice for
+    // shadow rays.
+    fn occluded(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> bool {
+        self.hit(ray, ray_t).is_some()
+    }
+
+    // Fraction of the light travelling along `ray` within `ray_t` that gets through. Surfaces
+    // block it entirely, while volumes let part of it through.
+    fn transmittance(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> f64 {
+        if self.occluded(ray, ray_t) {
+            0.0
+        } else {
+            1.0
+        }
+    }
+
+    // A box enclosing everything the hittable can be hit on, over the whole of its motion.
+    // None if it's unbounded or has nothing to hit.
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+
+    // Whether the hittable should be sampled as a light.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    // Solid angle density, as seen from `origin` at `time`, with which `random` picks
+    // `direction`.
+    fn pdf_value(&self, _origin: &Point3, _direction: &Vec3, _time: f64) -> f64 {
+        0.0
+    }
+
+    // Returns a random direction from `origin` towards the hittable where it is at `time`.
+    fn random(&self, _origin: &Point3, _time: f64) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+
+    fn area(&self) -> f64 {
+        0.0
+    }
+
+    // Calls `visit` with each material hits on the hittable can report, in a fixed order. Used
+    // to tell materials apart when debugging.
+    fn visit_materials(&self, _visit: &mut dyn FnMut(&Arc<dyn Material>)) {}
+
+    // Picks a point uniformly over the surface where it is at `time`, returning a record of it
+    // as if it had been hit from outside, with the outward normal.
+    fn sample_surface(&self, _time: f64) -> Option<HitRecord> {
+        None
+    }
 }
 
 pub struct Sphere {
+    // Center at time 0, and how far it moves per unit of time.
     center: Point3,
+    velocity: Vec3,
     radius: f64,
     mat: Arc<dyn Material>,
 }
 
 impl Sphere {
     pub fn new(center: Point3, radius: f64, mat: Arc<dyn Material>) -> Sphere {
+        Sphere::new_moving(center, center, radius, mat)
+    }
+
+    // A sphere moving in a straight line, from `center0` at time 0 to `center1` at time 1, and
+    // still before and after.
+    pub fn new_moving(
+        center0: Point3,
+        center1: Point3,
+        radius: f64,
+        mat: Arc<dyn Material>,
+    ) -> Sphere {
         Sphere {
-            center,
+            center: center0,
+            velocity: center1 - center0,
             radius,
             mat,
         }
     }
-}
 
-impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> Option<HitRecord> {
-        let oc = ray.origin - self.center; // center of sphere to origin of vector
+    fn center_at(&self, time: f64) -> Point3 {
+        self.center + self.velocity * time.clamp(0.0, 1.0)
+    }
+
+    // Longitude and latitude of the point with the given outward normal, with the poles along
+    // the y axis.
+    fn uv(outward_normal: &Vec3) -> (f64, f64) {
+        let theta = (-outward_normal.y).acos();
+        let phi = (-outward_normal.z).atan2(outward_normal.x) + PI;
+        (phi / (2.0 * PI), theta / PI)
+    }
+
+    // Direction of increasing longitude at the point with the given outward normal, around the
+    // y axis. Zero at the poles.
+    fn tangent(outward_normal: &Vec3) -> Vec3 {
+        let tangent = Vec3::new(outward_normal.z, 0.0, -outward_normal.x);
+        if tangent.is_near_zero() {
+            Vec3::default()
+        } else {
+            tangent.unit_vector()
+        }
+    }
+
+    // Returns the distance along `ray` to the nearest intersection within `ray_t`.
+    fn root(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> Option<f64> {
+        count_primitive_test();
+        let oc = ray.origin - self.center_at(ray.time); // center of sphere to origin of vector
         let a = ray.direction.length_squared();
         let half_b = ray.direction.dot(&oc);
         let c = oc.dot(&oc) - self.radius * self.radius;
@@ -84,17 +238,94 @@ impl Hittable for Sphere {
             };
         }
 
+        Some(root)
+    }
+}
+
+impl Hittable for Sphere {
+    fn hit(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> Option<HitRecord> {
+        let root = self.root(ray, ray_t)?;
         let p = ray.at(root);
 
-        let outward_normal = (p - self.center) / self.radius;
+        let outward_normal = (p - self.center_at(ray.time)) / self.radius;
         let (front_face, normal) = HitRecord::get_face_normal(ray, &outward_normal);
 
+        let (u, v) = Sphere::uv(&outward_normal);
+
         Some(HitRecord {
             t: root,
             p,
             normal,
             mat: Some(Arc::clone(&self.mat)),
             front_face,
+            u,
+            v,
+            tangent: Sphere::tangent(&outward_normal),
+            object: 0,
+        })
+    }
+
+    fn occluded(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> bool {
+        self.root(ray, ray_t).is_some()
+    }
+
+    // Covers the sphere from time 0 to time 1.
+    fn bounding_box(&self) -> Option<Aabb> {
+        let radius = Vec3::new(self.radius, self.radius, self.radius);
+        let start = Aabb::new(self.center - radius, self.center + radius);
+        Some(start.union(&start.offset(self.velocity)))
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.mat.is_emissive()
+    }
+
+    fn visit_materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        visit(&self.mat);
+    }
+
+    // NOTE: only valid for origins outside of the sphere.
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        let ray = Ray {
+            time,
+            ..Ray::new(*origin, *direction)
+        };
+        if self.hit(&ray, 0.001..=f64::INFINITY).is_none() {
+            return 0.0;
+        }
+
+        let distance_squared = (self.center_at(time) - *origin).length_squared();
+        let cos_theta_max = (1.0 - self.radius * self.radius / distance_squared)
+            .max(0.0)
+            .sqrt();
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+
+        1.0 / solid_angle
+    }
+
+    fn random(&self, origin: &Point3, time: f64) -> Vec3 {
+        let direction = self.center_at(time) - *origin;
+        let distance_squared = direction.length_squared();
+        Onb::new(direction.unit_vector())
+            .local(Vec3::random_to_sphere(self.radius, distance_squared))
+    }
+
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    fn sample_surface(&self, time: f64) -> Option<HitRecord> {
+        let normal = Vec3::random_unit_vector();
+        let (u, v) = Sphere::uv(&normal);
+        Some(HitRecord {
+            p: self.center_at(time) + normal * self.radius,
+            normal,
+            mat: Some(Arc::clone(&self.mat)),
+            front_face: true,
+            u,
+            v,
+            tangent: Sphere::tangent(&normal),
+            ..HitRecord::default()
         })
     }
 }
@@ -106,9 +337,10 @@ pub struct Triangle {
     mat: Arc<dyn Material>,
     p1_p2: Vec3,
     p1_p3: Vec3,
-    p2_p3: Vec3,
     normal: Vec3,
     d: f64,
+    // Texture coordinates of `p1`, `p2` and `p3`.
+    tex_coords: Option<[[f64; 2]; 3]>,
 }
 
 pub enum Translation {
@@ -121,11 +353,11 @@ pub enum Translation {
 }
 
 impl Triangle {
+    // The front face is the one the vertices wind counter-clockwise around, as in glTF.
     pub fn new(p1: Point3, p2: Point3, p3: Point3, mat: Arc<dyn Material>) -> Triangle {
         let p1_p2 = p2 - p1;
         let p1_p3 = p3 - p1;
-        let p2_p3 = p3 - p2;
-        let normal = (p1_p3).cross(&p1_p2).unit_vector();
+        let normal = (p1_p2).cross(&p1_p3).unit_vector();
 
         // Finding the D such that the equation normal . point_on_plane = D is satisfied
         let d = normal.dot(&(p1));
@@ -137,23 +369,26 @@ impl Triangle {
             mat,
             p1_p2,
             p1_p3,
-            p2_p3,
             normal,
             d,
+            tex_coords: None,
         }
     }
 
+    // The front face is the one `outward` points away from, whatever the winding.
     pub fn new_with_normal(
         p1: Point3,
         p2: Point3,
         p3: Point3,
-        normal: Vec3,
+        outward: Vec3,
         mat: Arc<dyn Material>,
     ) -> Triangle {
         let p1_p2 = p2 - p1;
         let p1_p3 = p3 - p1;
-        let p2_p3 = p3 - p2;
-        let normal = (p1_p3).cross(&p1_p2).unit_vector();
+        let mut normal = (p1_p2).cross(&p1_p3).unit_vector();
+        if normal.dot(&outward) < 0.0 {
+            normal = -normal;
+        }
 
         // Finding the D such that the equation normal . point_on_plane = D is satisfied
         let d = normal.dot(&(p1));
@@ -165,12 +400,18 @@ impl Triangle {
             mat,
             p1_p2,
             p1_p3,
-            p2_p3,
             normal,
             d,
+            tex_coords: None,
         }
     }
 
+    // Gives the vertices texture coordinates, which hits report interpolated.
+    pub fn with_tex_coords(mut self, tex_coords: [[f64; 2]; 3]) -> Triangle {
+        self.tex_coords = Some(tex_coords);
+        self
+    }
+
     pub fn translate(self, translation: Translation) -> Self {
         let translation_vec = match translation {
             Translation::Left(amt) => Vec3::new(-amt, 0.0, 0.0),
@@ -180,42 +421,89 @@ impl Triangle {
             Translation::Forward(amt) => Vec3::new(0.0, 0.0, amt),
             Translation::Backward(amt) => Vec3::new(0.0, 0.0, -amt),
         };
-        Triangle::new(
-            self.p1 + translation_vec,
-            self.p2 + translation_vec,
-            self.p3 + translation_vec,
-            self.mat,
-        )
+        Triangle {
+            tex_coords: self.tex_coords,
+            ..Triangle::new_with_normal(
+                self.p1 + translation_vec,
+                self.p2 + translation_vec,
+                self.p3 + translation_vec,
+                self.normal,
+                self.mat,
+            )
+        }
     }
 
     pub fn scale(self, scalar: f64) -> Self {
-        Triangle::new_with_normal(
-            self.p1 * scalar,
-            self.p2 * scalar,
-            self.p3 * scalar,
-            self.normal,
-            self.mat,
-        )
+        Triangle {
+            tex_coords: self.tex_coords,
+            ..Triangle::new_with_normal(
+                self.p1 * scalar,
+                self.p2 * scalar,
+                self.p3 * scalar,
+                self.normal,
+                self.mat,
+            )
+        }
     }
-}
 
-impl Hittable for Triangle {
-    fn hit(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> Option<HitRecord> {
-        let normal = if self.normal.dot(&ray.direction) > 0.0 {
-            -self.normal
+    // Surface coordinates at the point with barycentric weights `b2` and `b3` for `p2` and
+    // `p3`.
+    fn uv(&self, b2: f64, b3: f64) -> (f64, f64) {
+        match self.tex_coords {
+            Some([t1, t2, t3]) => {
+                let b1 = 1.0 - b2 - b3;
+                (
+                    b1 * t1[0] + b2 * t2[0] + b3 * t3[0],
+                    b1 * t1[1] + b2 * t2[1] + b3 * t3[1],
+                )
+            }
+            None => (b2, b3),
+        }
+    }
+
+    // Direction in which `u` increases across the triangle, as glTF defines tangents: along the
+    // first texture coordinate, or towards `p2` without texture coordinates.
+    fn tangent(&self) -> Vec3 {
+        let mut tangent = self.p1_p2;
+        if let Some([t1, t2, t3]) = self.tex_coords {
+            let (du2, dv2) = (t2[0] - t1[0], t2[1] - t1[1]);
+            let (du3, dv3) = (t3[0] - t1[0], t3[1] - t1[1]);
+            let determinant = du2 * dv3 - du3 * dv2;
+            if determinant.abs() > 1e-12 {
+                tangent = (self.p1_p2 * dv3 - self.p1_p3 * dv2) / determinant;
+            }
+        }
+        let tangent = tangent - self.normal * tangent.dot(&self.normal);
+        if tangent.is_near_zero() {
+            Vec3::default()
         } else {
-            self.normal
-        };
+            tangent.unit_vector()
+        }
+    }
 
-        let discriminant = normal.dot(&ray.direction);
+    // Returns the distance along `ray` to the intersection if it lies within `ray_t`, along with
+    // the point of intersection, the normal facing the ray and the barycentric weights of `p2`
+    // and `p3`.
+    fn intersect(
+        &self,
+        ray: &Ray,
+        ray_t: RangeInclusive<f64>,
+    ) -> Option<(f64, Point3, Vec3, f64, f64)> {
+        count_primitive_test();
+        let discriminant = self.normal.dot(&ray.direction);
 
         // If the ray is parallel to the plane containing the triangle,
         // then it does not intersect the triangle
-        if discriminant >= 0.0 {
+        if discriminant == 0.0 {
             return None;
         }
 
-        let t = (self.d - normal.dot(&ray.origin)) / discriminant;
+        let t = (self.d - self.normal.dot(&ray.origin)) / discriminant;
+        let normal = if discriminant > 0.0 {
+            -self.normal
+        } else {
+            self.normal
+        };
 
         if !ray_t.contains(&t) {
             return None;
@@ -223,44 +511,533 @@ impl Hittable for Triangle {
 
         let point_of_intersection = ray.origin + ray.direction * t;
 
-        let double_triangle_area = self.p1_p3.cross(&self.p1_p2).length();
-
+        // Barycentric weights from the signed areas of the triangles the point makes with each
+        // edge, which are negative outside that edge. Comparing unsigned areas instead would
+        // depend on their sum matching the whole exactly, which rounding often breaks.
+        let double_area_normal = self.p1_p2.cross(&self.p1_p3);
+        let double_area_squared = double_area_normal.length_squared();
         let p1_poi = point_of_intersection - self.p1;
-        let alpha = self.p1_p3.cross(&p1_poi).length();
-        let beta = self.p1_p2.cross(&p1_poi).length();
-        let gamma = self
-            .p2_p3
-            .cross(&(point_of_intersection - self.p2))
-            .length();
-
-        if alpha + beta + gamma <= double_triangle_area {
-            Some(HitRecord {
-                p: point_of_intersection,
-                normal,
-                mat: Some(self.mat.clone()),
-                t,
-                front_face: true,
-            })
+        let u = p1_poi.cross(&self.p1_p3).dot(&double_area_normal) / double_area_squared;
+        let v = self.p1_p2.cross(&p1_poi).dot(&double_area_normal) / double_area_squared;
+
+        if u >= 0.0 && v >= 0.0 && u + v <= 1.0 {
+            Some((t, point_of_intersection, normal, u, v))
         } else {
             None
         }
     }
 }
 
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> Option<HitRecord> {
+        let (t, p, normal, b2, b3) = self.intersect(ray, ray_t)?;
+        let (u, v) = self.uv(b2, b3);
+        Some(HitRecord {
+            p,
+            normal,
+            mat: Some(self.mat.clone()),
+            t,
+            front_face: ray.direction.dot(&self.normal) < 0.0,
+            u,
+            v,
+            tangent: self.tangent(),
+            object: 0,
+        })
+    }
+
+    fn occluded(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> bool {
+        self.intersect(ray, ray_t).is_some()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.p1, self.p2).union(&Aabb::new(self.p3, self.p3)))
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.mat.is_emissive()
+    }
+
+    fn visit_materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        visit(&self.mat);
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, _time: f64) -> f64 {
+        let Some(record) = self.hit(&Ray::new(*origin, *direction), 0.001..=f64::INFINITY) else {
+            return 0.0;
+        };
+
+        let area = self.area();
+        let distance_squared = record.t * record.t * direction.length_squared();
+        let cosine = (direction.dot(&self.normal) / direction.length()).abs();
+
+        distance_squared / (cosine * area)
+    }
+
+    fn random(&self, origin: &Point3, time: f64) -> Vec3 {
+        self.sample_surface(time).unwrap().p - *origin
+    }
+
+    fn area(&self) -> f64 {
+        0.5 * self.p1_p3.cross(&self.p1_p2).length()
+    }
+
+    fn sample_surface(&self, _time: f64) -> Option<HitRecord> {
+        // Uniformly distributed barycentric coordinates
+        let su = get_random_f64().sqrt();
+        let b2 = get_random_f64() * su;
+        let b3 = su - b2;
+        let (u, v) = self.uv(b2, b3);
+        Some(HitRecord {
+            p: self.p1 + self.p1_p2 * b2 + self.p1_p3 * b3,
+            normal: self.normal,
+            mat: Some(self.mat.clone()),
+            front_face: true,
+            u,
+            v,
+            tangent: self.tangent(),
+            ..HitRecord::default()
+        })
+    }
+}
+
+// Where a moving object is at some moment: rotated about its own origin, then moved.
+#[derive(Clone, Copy)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quaternion<f64>,
+}
+
+impl Transform {
+    // Rotates by `degrees` about `axis`, then moves by `translation`.
+    pub fn new(translation: Vec3, axis: Vec3, degrees: f64) -> Transform {
+        let axis = axis.unit_vector();
+        Transform {
+            translation,
+            rotation: Quaternion::from_axis_angle(
+                Vector3::new(axis.x, axis.y, axis.z),
+                Deg(degrees),
+            ),
+        }
+    }
+
+    pub fn translation(translation: Vec3) -> Transform {
+        Transform {
+            translation,
+            rotation: Quaternion::one(),
+        }
+    }
+
+    fn point(&self, p: Point3) -> Point3 {
+        self.vector(p) + self.translation
+    }
+
+    fn vector(&self, v: Vec3) -> Vec3 {
+        let v = self.rotation.rotate_vector(Vector3::new(v.x, v.y, v.z));
+        Vec3::new(v.x, v.y, v.z)
+    }
+
+    fn inverse_point(&self, p: Point3) -> Point3 {
+        self.inverse_vector(p - self.translation)
+    }
+
+    fn inverse_vector(&self, v: Vec3) -> Vec3 {
+        let v = self
+            .rotation
+            .invert()
+            .rotate_vector(Vector3::new(v.x, v.y, v.z));
+        Vec3::new(v.x, v.y, v.z)
+    }
+
+    // The box enclosing `bounds` once transformed.
+    fn bounds(&self, bounds: &Aabb) -> Aabb {
+        bounds
+            .corners()
+            .into_iter()
+            .map(|corner| {
+                let p = self.point(corner);
+                Aabb::new(p, p)
+            })
+            .reduce(|a, b| a.union(&b))
+            .unwrap()
+    }
+}
+
+impl Lerp for Transform {
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        Transform {
+            translation: self.translation.lerp(&other.translation, t),
+            rotation: self.rotation.lerp(&other.rotation, t),
+        }
+    }
+}
+
+// An object moved and turned as a whole over time, such as a mesh or another list, placed by
+// the transform at the time of each ray. Its transform is keyframed, and holds still before
+// the first and after the last keyframe. Emissive objects are sampled as lights where they
+// are at the time of the path sampling them.
+pub struct Moving {
+    object: Box<dyn Hittable>,
+    transforms: Keyframes<Transform>,
+}
+
+impl Moving {
+    // Moves the object in a straight line by `velocity` from time 0 to time 1.
+    pub fn new(object: Box<dyn Hittable>, velocity: Vec3) -> Moving {
+        Moving::new_keyframed(
+            object,
+            Keyframes::new(vec![
+                (0.0, Transform::translation(Vec3::default())),
+                (1.0, Transform::translation(velocity)),
+            ]),
+        )
+    }
+
+    pub fn new_keyframed(object: Box<dyn Hittable>, transforms: Keyframes<Transform>) -> Moving {
+        Moving { object, transforms }
+    }
+
+    // The ray in the object's frame at the time the ray samples, where the object is still.
+    fn local_ray(&self, ray: &Ray) -> (Transform, Ray) {
+        let transform = self.transforms.at(ray.time);
+        let local = Ray {
+            origin: transform.inverse_point(ray.origin),
+            direction: transform.inverse_vector(ray.direction),
+            ..*ray
+        };
+        (transform, local)
+    }
+
+    // Takes a record from the object's frame back to the world's.
+    fn world_record(transform: &Transform, record: HitRecord) -> HitRecord {
+        HitRecord {
+            p: transform.point(record.p),
+            normal: transform.vector(record.normal),
+            tangent: transform.vector(record.tangent),
+            ..record
+        }
+    }
+}
+
+impl Hittable for Moving {
+    fn hit(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> Option<HitRecord> {
+        let (transform, local) = self.local_ray(ray);
+        let record = self.object.hit(&local, ray_t)?;
+        Some(Moving::world_record(&transform, record))
+    }
+
+    fn occluded(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> bool {
+        let (_, local) = self.local_ray(ray);
+        self.object.occluded(&local, ray_t)
+    }
+
+    fn transmittance(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> f64 {
+        let (_, local) = self.local_ray(ray);
+        self.object.transmittance(&local, ray_t)
+    }
+
+    // Covers the object over all of its keyframes. Between two of them, no point of the object
+    // travels further than the distance moved plus the arc it turns through, so the boxes at
+    // either end are grown by that much.
+    fn bounding_box(&self) -> Option<Aabb> {
+        let bounds = self.object.bounding_box()?;
+        let reach = [bounds.min, bounds.max]
+            .iter()
+            .map(|corner| corner.x.abs().max(corner.y.abs()).max(corner.z.abs()))
+            .fold(0.0, f64::max)
+            * 3.0_f64.sqrt();
+
+        let keys = self.transforms.keys();
+        let mut swept = keys[0].1.bounds(&bounds);
+        for pair in keys.windows(2) {
+            let (from, to) = (&pair[0].1, &pair[1].1);
+            let angle = 2.0 * from.rotation.dot(to.rotation).abs().min(1.0).acos();
+            let travel = (to.translation - from.translation).length() + reach * angle;
+            swept = swept.union(&from.bounds(&bounds).union(&to.bounds(&bounds)).pad(travel));
+        }
+        Some(swept)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.object.is_emissive()
+    }
+
+    fn visit_materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        self.object.visit_materials(visit);
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        let transform = self.transforms.at(time);
+        self.object.pdf_value(
+            &transform.inverse_point(*origin),
+            &transform.inverse_vector(*direction),
+            time,
+        )
+    }
+
+    fn random(&self, origin: &Point3, time: f64) -> Vec3 {
+        let transform = self.transforms.at(time);
+        transform.vector(self.object.random(&transform.inverse_point(*origin), time))
+    }
+
+    // Transforms are rigid, so the area doesn't change.
+    fn area(&self) -> f64 {
+        self.object.area()
+    }
+
+    fn sample_surface(&self, time: f64) -> Option<HitRecord> {
+        let record = self.object.sample_surface(time)?;
+        Some(Moving::world_record(&self.transforms.at(time), record))
+    }
+}
+
 pub type HittableList = Vec<Box<dyn Hittable>>;
 
 impl Hittable for HittableList {
     fn hit(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> Option<HitRecord> {
+        count_node_test();
         let mut smallest_range_so_far = ray_t;
 
         let mut record = None;
 
-        for hittable in self {
-            if let Some(hittable_record) = (*hittable).hit(ray, smallest_range_so_far.clone()) {
+        for (object, hittable) in self.iter().enumerate() {
+            if let Some(mut hittable_record) = (*hittable).hit(ray, smallest_range_so_far.clone()) {
                 smallest_range_so_far = *smallest_range_so_far.start()..=hittable_record.t;
+                hittable_record.object = object;
                 record = Some(hittable_record);
             }
         }
         record
     }
+
+    fn occluded(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> bool {
+        count_node_test();
+        self.iter()
+            .any(|hittable| hittable.occluded(ray, ray_t.clone()))
+    }
+
+    fn transmittance(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> f64 {
+        count_node_test();
+        let mut transmittance = 1.0;
+        for hittable in self {
+            transmittance *= hittable.transmittance(ray, ray_t.clone());
+            if transmittance == 0.0 {
+                break;
+            }
+        }
+        transmittance
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.iter()
+            .filter_map(|hittable| hittable.bounding_box())
+            .reduce(|a, b| a.union(&b))
+    }
+
+    fn visit_materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        for hittable in self {
+            hittable.visit_materials(visit);
+        }
+    }
+
+    // A list is sampled as a light through its emissive members, so that a mesh can be moved
+    // or nested as a whole and still light the scene.
+    fn is_emissive(&self) -> bool {
+        self.iter().any(|hittable| hittable.is_emissive())
+    }
+
+    // `random` picks an emissive member uniformly, so any of them could have picked `direction`.
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        let (count, sum) = self
+            .iter()
+            .filter(|hittable| hittable.is_emissive())
+            .fold((0, 0.0), |(count, sum), hittable| {
+                (count + 1, sum + hittable.pdf_value(origin, direction, time))
+            });
+        if count == 0 {
+            0.0
+        } else {
+            sum / count as f64
+        }
+    }
+
+    fn random(&self, origin: &Point3, time: f64) -> Vec3 {
+        let emissive: Vec<_> = self
+            .iter()
+            .filter(|hittable| hittable.is_emissive())
+            .collect();
+        if emissive.is_empty() {
+            return Vec3::new(1.0, 0.0, 0.0);
+        }
+        let index = ((get_random_f64() * emissive.len() as f64) as usize).min(emissive.len() - 1);
+        emissive[index].random(origin, time)
+    }
+
+    // Area of the emissive members, which `sample_surface` picks points over.
+    fn area(&self) -> f64 {
+        self.iter()
+            .filter(|hittable| hittable.is_emissive())
+            .map(|hittable| hittable.area())
+            .sum()
+    }
+
+    // Picks an emissive member in proportion to its area, so points are uniform over all of
+    // them.
+    fn sample_surface(&self, time: f64) -> Option<HitRecord> {
+        let mut remaining = get_random_f64() * self.area();
+        let mut last = None;
+        for hittable in self.iter().filter(|hittable| hittable.is_emissive()) {
+            remaining -= hittable.area();
+            last = Some(hittable);
+            if remaining <= 0.0 {
+                break;
+            }
+        }
+        last?.sample_surface(time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::material::Lambertian;
+
+    fn triangle() -> Triangle {
+        // Counter-clockwise seen from +z.
+        Triangle::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+            Arc::new(Lambertian {
+                albedo: Color::new(0.5, 0.5, 0.5),
+            }),
+        )
+    }
+
+    #[test]
+    fn triangle_front_face_follows_winding() {
+        let triangle = triangle();
+
+        let from_front = Ray::new(Point3::new(0.25, 0.25, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let record = triangle.hit(&from_front, 0.001..=f64::INFINITY).unwrap();
+        assert!(record.front_face);
+        assert_eq!(record.normal.z, 1.0);
+        assert_eq!(record.t, 1.0);
+
+        let from_back = Ray::new(Point3::new(0.25, 0.25, -1.0), Vec3::new(0.0, 0.0, 1.0));
+        let record = triangle.hit(&from_back, 0.001..=f64::INFINITY).unwrap();
+        assert!(!record.front_face);
+        assert_eq!(record.normal.z, -1.0);
+    }
+
+    #[test]
+    fn triangle_front_face_follows_given_normal() {
+        let triangle = Triangle::new_with_normal(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Arc::new(Lambertian {
+                albedo: Color::new(0.5, 0.5, 0.5),
+            }),
+        );
+        let from_back = Ray::new(Point3::new(0.25, 0.25, -1.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(
+            triangle
+                .hit(&from_back, 0.001..=f64::INFINITY)
+                .unwrap()
+                .front_face
+        );
+    }
+
+    #[test]
+    fn triangle_inside_test_uses_barycentric_coordinates() {
+        let triangle = triangle();
+        let hit = |x: f64, y: f64| {
+            let ray = Ray::new(Point3::new(x, y, 1.0), Vec3::new(0.0, 0.0, -1.0));
+            triangle.hit(&ray, 0.001..=f64::INFINITY)
+        };
+
+        let record = hit(0.2, 0.3).unwrap();
+        assert!((record.u - 0.2).abs() < 1e-12 && (record.v - 0.3).abs() < 1e-12);
+        // Points on the long edge, where the old unsigned-area test was at the mercy of rounding.
+        for i in 1..100 {
+            let x = f64::from(i) / 100.0;
+            assert!(hit(x, 1.0 - x - 1e-9).is_some(), "missed at x = {x}");
+        }
+        assert!(hit(0.6, 0.6).is_none());
+        assert!(hit(-0.1, 0.5).is_none());
+        assert!(hit(0.5, -0.1).is_none());
+    }
+
+    #[test]
+    fn moving_objects_are_hit_where_they_are_at_the_ray_time() {
+        // The triangle turns a quarter turn about z over a second, moving 2 along x.
+        let moving = Moving::new_keyframed(
+            Box::new(triangle()),
+            Keyframes::new(vec![
+                (0.0, Transform::translation(Vec3::default())),
+                (
+                    1.0,
+                    Transform::new(Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 90.0),
+                ),
+            ]),
+        );
+        let ray_at = |x: f64, y: f64, time: f64| Ray {
+            time,
+            ..Ray::new(Point3::new(x, y, 1.0), Vec3::new(0.0, 0.0, -1.0))
+        };
+
+        assert!(moving
+            .hit(&ray_at(0.25, 0.25, 0.0), 0.001..=f64::INFINITY)
+            .is_some());
+        assert!(moving
+            .hit(&ray_at(0.25, 0.25, 1.0), 0.001..=f64::INFINITY)
+            .is_none());
+        // At time 1, the triangle's x axis points along y.
+        let record = moving
+            .hit(&ray_at(1.75, 0.25, 1.0), 0.001..=f64::INFINITY)
+            .unwrap();
+        assert!((record.p.x - 1.75).abs() < 1e-9 && record.front_face);
+        assert!((record.normal.z - 1.0).abs() < 1e-9);
+
+        // The bounds cover every point of the triangle along the way.
+        let bounds = moving.bounding_box().unwrap();
+        for i in 0..=20 {
+            let transform = moving.transforms.at(f64::from(i) / 20.0);
+            for corner in [triangle().p1, triangle().p2, triangle().p3] {
+                let p = transform.point(corner);
+                for axis in 0..3 {
+                    assert!(bounds.min[axis] <= p[axis] && p[axis] <= bounds.max[axis]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tangents_follow_texture_coordinates() {
+        // Texture u runs along y across the triangle.
+        let triangle = triangle().with_tex_coords([[0.0, 0.0], [0.0, 1.0], [1.0, 0.0]]);
+        let ray = Ray::new(Point3::new(0.2, 0.3, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let record = triangle.hit(&ray, 0.001..=f64::INFINITY).unwrap();
+        assert!((record.tangent.y - 1.0).abs() < 1e-12);
+
+        // On spheres the tangent turns smoothly with the normal, unlike an `Onb` basis, which
+        // swaps axes where the normal's x passes 0.9.
+        let sphere = Sphere::new(
+            Point3::default(),
+            1.0,
+            Arc::new(Lambertian {
+                albedo: Color::new(0.5, 0.5, 0.5),
+            }),
+        );
+        let tangent_at = |x: f64| {
+            let target = Point3::new(x, 0.0, (1.0 - x * x).sqrt());
+            let ray = Ray::new(target * 2.0, -target);
+            let record = sphere.hit(&ray, 0.001..=f64::INFINITY).unwrap();
+            assert!(record.tangent.dot(&record.normal).abs() < 1e-9);
+            record.tangent
+        };
+        assert!(tangent_at(0.899).dot(&tangent_at(0.901)) > 0.99);
+    }
 }
@@ -0,0 +1,368 @@
+use rayon::prelude::*;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::camera::{Camera, SAMPLES_PER_PIXEL};
+use crate::color::Color;
+use crate::film::Film;
+use crate::hittable::{take_intersection_tests, Hittable};
+use crate::light::LightList;
+use crate::material::{emitted_radiance, Material};
+use crate::onb::Onb;
+use crate::photon::PhotonMap;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::spectrum::{film_rgb, upsample};
+use crate::utils::{get_random_f64, power_heuristic};
+use crate::vec::Vec3;
+
+// Paths are cut off after `MAX_DEPTH` bounces, and may be terminated by Russian roulette
+// once they have made `MIN_DEPTH` bounces.
+pub const MAX_DEPTH: i32 = 50;
+const MIN_DEPTH: i32 = 3;
+
+// Everything an integrator can use while rendering an image.
+pub struct RenderContext<'a> {
+    pub scene: &'a Scene,
+    pub lights: LightList<'a>,
+    pub camera: &'a Camera,
+    // Light added to pixels other than the one being sampled, such as by light tracing.
+    pub splats: Film,
+}
+
+// A light transport algorithm. The camera generates rays, and the integrator computes the
+// radiance arriving along them.
+//
+// In spectral mode, `wavelength` is the wavelength the path follows and the returned color is
+// a grey holding the radiance at that wavelength.
+pub trait Integrator: Sync {
+    fn radiance(&self, ray: &Ray, context: &RenderContext, wavelength: Option<f64>) -> Color;
+
+    // Renders the image, returning the color of every pixel in row order, summed over
+    // `SAMPLES_PER_PIXEL` samples. By default each pixel is estimated on its own from camera
+    // rays through it; integrators that choose where to sample the image themselves override
+    // this.
+    fn render(&self, context: &RenderContext) -> Vec<Color> {
+        let camera = context.camera;
+        let (width, height) = camera.resolution();
+        (0..height)
+            .into_par_iter()
+            .flat_map(|j| {
+                (0..width).into_par_iter().map(move |i| {
+                    let mut pixel_color = Color::default();
+                    for _ in 0..SAMPLES_PER_PIXEL {
+                        let ray = camera.sample_ray(i, j);
+                        let radiance = self.radiance(&ray, context, ray.wavelength);
+                        pixel_color += film_rgb(radiance, ray.wavelength);
+                    }
+                    pixel_color
+                })
+            })
+            .collect()
+    }
+}
+
+// Follows BSDF samples until they escape or hit an emitter, without sampling lights directly.
+// Delta lights are never found this way.
+pub struct NaivePathTracer;
+
+impl Integrator for NaivePathTracer {
+    fn radiance(&self, ray: &Ray, context: &RenderContext, wavelength: Option<f64>) -> Color {
+        let scene = context.scene;
+        let lights = &context.lights;
+        let mut radiance = Color::default();
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+
+        for depth in 0..MAX_DEPTH {
+            let hit = scene.hit(&ray, 0.001..=f64::INFINITY);
+            let t_max = hit.as_ref().map_or(f64::INFINITY, |record| record.t);
+            radiance += throughput
+                * lights
+                    .hit_le(&ray, 0.001..=t_max, wavelength)
+                    .unwrap_or_default();
+
+            let Some(record) = hit else {
+                radiance += throughput * upsample(scene.background(&ray), wavelength);
+                break;
+            };
+            let mat = record.mat.clone().unwrap();
+            radiance += throughput * emitted_radiance(mat.as_ref(), &ray, &record, wavelength);
+
+            let Some(sample) = mat.sample(&ray, &record) else {
+                break;
+            };
+            let Some(survivor) =
+                russian_roulette(throughput * upsample(sample.weight, wavelength), depth)
+            else {
+                break;
+            };
+            throughput = survivor;
+            ray = sample.ray;
+        }
+
+        radiance
+    }
+}
+
+// A path tracer that samples a light directly at every non-specular hit (next-event
+// estimation), and combines its contribution with the one found by following the BSDF sample
+// using the power heuristic.
+pub struct PathTracer;
+
+impl Integrator for PathTracer {
+    fn radiance(&self, ray: &Ray, context: &RenderContext, wavelength: Option<f64>) -> Color {
+        trace_path(ray, context, wavelength, false, None)
+    }
+}
+
+// Only accounts for light reaching a surface straight from an emitter, though paths are still
+// followed through mirrors and glass.
+pub struct DirectLighting;
+
+impl Integrator for DirectLighting {
+    fn radiance(&self, ray: &Ray, context: &RenderContext, wavelength: Option<f64>) -> Color {
+        trace_path(ray, context, wavelength, true, None)
+    }
+}
+
+// Shades the first hit along each camera ray by the fraction of `samples` cosine-distributed
+// rays that leave it without hitting anything within `radius`, with volumes in the way letting
+// their transmittance through. Lights and materials are ignored.
+pub struct AmbientOcclusion {
+    pub samples: u32,
+    pub radius: f64,
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance(&self, ray: &Ray, context: &RenderContext, wavelength: Option<f64>) -> Color {
+        let world = &context.scene.world;
+        let Some(record) = world.hit(ray, 0.001..=f64::INFINITY) else {
+            return Color::new(1.0, 1.0, 1.0);
+        };
+
+        let onb = Onb::new(record.normal);
+        let unoccluded: f64 = (0..self.samples)
+            .map(|_| {
+                let occlusion_ray = ray.spawn(record.p, onb.local(Vec3::random_cosine_direction()));
+                world.transmittance(&occlusion_ray, 0.001..=self.radius)
+            })
+            .sum();
+
+        let visibility = unoccluded / f64::from(self.samples.max(1));
+        upsample(Color::new(visibility, visibility, visibility), wavelength)
+    }
+}
+
+// Shades the first hit along each camera ray by one of its properties rather than by light
+// transport, to inspect the scene's geometry directly.
+pub enum DebugView {
+    // Normal facing the ray, mapped from [-1, 1] to [0, 1] per channel.
+    Normals,
+    // Hit distance, from white at the camera fading to black at `max_distance`.
+    Depth { max_distance: f64 },
+    // Surface coordinates in the red and green channels.
+    Uv,
+    // A distinct color for each material, from its position in the world, keyed by the address
+    // of the material. Built by `DebugView::materials`.
+    Material { ids: HashMap<usize, usize> },
+    // A distinct color for each object in the world.
+    Object,
+    // A heatmap of the list nodes plus primitives tested, reaching red at `max_tests`. Tests
+    // are only counted with the `intersection-stats` feature.
+    Cost { max_tests: u32 },
+}
+
+impl DebugView {
+    // Colors each material of `scene` by the order its first use appears in the world, so that
+    // it keeps its color from one run to the next.
+    pub fn materials(scene: &Scene) -> DebugView {
+        let mut ids = HashMap::new();
+        scene.world.visit_materials(&mut |mat| {
+            let next = ids.len();
+            ids.entry(material_key(mat)).or_insert(next);
+        });
+        DebugView::Material { ids }
+    }
+}
+
+fn material_key(mat: &Arc<dyn Material>) -> usize {
+    Arc::as_ptr(mat).cast::<()>() as usize
+}
+
+impl Integrator for DebugView {
+    fn radiance(&self, ray: &Ray, context: &RenderContext, wavelength: Option<f64>) -> Color {
+        take_intersection_tests();
+        let hit = context.scene.world.hit(ray, 0.001..=f64::INFINITY);
+        let (nodes, primitives) = take_intersection_tests();
+
+        let color = match (self, hit) {
+            (DebugView::Cost { max_tests }, _) => {
+                heatmap(f64::from(nodes + primitives) / f64::from(*max_tests))
+            }
+            (_, None) => Color::default(),
+            (DebugView::Normals, Some(record)) => (record.normal + Color::new(1.0, 1.0, 1.0)) * 0.5,
+            (DebugView::Depth { max_distance }, Some(record)) => {
+                let depth = 1.0 - (record.t * ray.direction.length() / max_distance).min(1.0);
+                Color::new(depth, depth, depth)
+            }
+            (DebugView::Uv, Some(record)) => Color::new(record.u, record.v, 0.0),
+            (DebugView::Material { ids }, Some(record)) => {
+                let key = material_key(record.mat.as_ref().unwrap());
+                ids.get(&key)
+                    .map_or(Color::default(), |&id| false_color(id))
+            }
+            (DebugView::Object, Some(record)) => false_color(record.object),
+        };
+
+        upsample(color, wavelength)
+    }
+}
+
+// Maps `heat` in [0, 1] onto a blue, green, red ramp.
+fn heatmap(heat: f64) -> Color {
+    let heat = heat.clamp(0.0, 1.0);
+    if heat < 0.5 {
+        Color::new(0.0, 2.0 * heat, 1.0 - 2.0 * heat)
+    } else {
+        Color::new(2.0 * heat - 1.0, 2.0 - 2.0 * heat, 0.0)
+    }
+}
+
+// Hashes `id` to a bright color, so that neighbouring ids get unrelated colors.
+fn false_color(id: usize) -> Color {
+    let hash = (id as u64 ^ 0x5851_f42d_4c95_7f2d).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    let channel = |shift: u32| 0.2 + 0.8 * f64::from((hash >> shift) as u8) / 255.0;
+    Color::new(channel(40), channel(48), channel(56))
+}
+
+// Path tracing with next-event estimation. With `direct_only`, the path ends after the BSDF
+// sample leaving the first non-specular hit, counting only the emission that sample finds.
+//
+// With `caustics`, light reaching the first non-specular hit through a chain of specular
+// bounces is read from the photon map instead, so emitters found through specular bounces
+// from that hit aren't counted, apart from lights that shoot no photons. Past the next
+// non-specular bounce, paths count everything again, as the map only holds light that landed
+// straight after specular bounces.
+pub fn trace_path(
+    ray: &Ray,
+    context: &RenderContext,
+    wavelength: Option<f64>,
+    direct_only: bool,
+    caustics: Option<&PhotonMap>,
+) -> Color {
+    let scene = context.scene;
+    let lights = &context.lights;
+    let mut radiance = Color::default();
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    let mut ray = *ray;
+
+    // The density of the BSDF sample that produced `ray`, for weighting lights it hits.
+    let mut bsdf_pdf = 0.0;
+    let mut specular_bounce = true;
+    let mut last_bounce = false;
+    // Whether the photon map has been read, and whether the path has only bounced specularly
+    // since, so emitters it finds are already in the map.
+    let mut gathered = false;
+    let mut skip_emission = false;
+
+    for depth in 0..MAX_DEPTH {
+        let hit = scene.hit(&ray, 0.001..=f64::INFINITY);
+
+        // Emission found by following the path, from analytic lights in front of the hit and
+        // from the hit itself.
+        let t_max = hit.as_ref().map_or(f64::INFINITY, |record| record.t);
+        let mut emitted = lights
+            .hit_le(&ray, 0.001..=t_max, wavelength)
+            .unwrap_or_default();
+        if let Some(record) = &hit {
+            let mat = record.mat.clone().unwrap();
+            emitted += emitted_radiance(mat.as_ref(), &ray, record, wavelength);
+        }
+        if specular_bounce {
+            if !skip_emission {
+                radiance += throughput * emitted;
+            } else if let Some(emitted) =
+                lights.hit_le_without_rays(&ray, 0.001..=t_max, wavelength)
+            {
+                // Lights that shoot no photons aren't in the map, so they're still counted.
+                radiance += throughput * emitted;
+            }
+        } else if !emitted.is_near_zero() {
+            let light_pdf = lights.pdf_value(&ray.origin, &ray.direction, ray.time);
+            radiance += throughput * emitted * power_heuristic(bsdf_pdf, light_pdf);
+        }
+
+        let Some(record) = hit else {
+            radiance += throughput * upsample(scene.background(&ray), wavelength);
+            break;
+        };
+        if last_bounce {
+            break;
+        }
+        let mat = record.mat.clone().unwrap();
+
+        let wo = -ray.direction.unit_vector();
+        if let Some(light) = lights.sample(&record.p, wavelength, ray.time) {
+            let f = upsample(mat.eval(&wo, &light.wi, &record), wavelength);
+            if !f.is_near_zero() && light.pdf > 0.0 {
+                let shadow_ray = ray.spawn(record.p, light.wi);
+                let transmittance =
+                    scene.transmittance(&shadow_ray, 0.001..=light.distance - 0.001);
+                if transmittance > 0.0 {
+                    let weight = if light.is_delta {
+                        1.0
+                    } else {
+                        power_heuristic(light.pdf, mat.pdf(&wo, &light.wi, &record))
+                    };
+                    radiance +=
+                        throughput * f * light.radiance * (transmittance * weight / light.pdf);
+                }
+            }
+        }
+
+        let sample = mat.sample(&ray, &record);
+        let gather = caustics.is_some()
+            && !gathered
+            && !sample.as_ref().is_some_and(|sample| sample.is_delta);
+        if let Some(caustics) = caustics.filter(|_| gather) {
+            radiance += throughput * upsample(caustics.estimate(&wo, &record), wavelength);
+            gathered = true;
+        }
+
+        let Some(sample) = sample else {
+            break;
+        };
+        let Some(survivor) =
+            russian_roulette(throughput * upsample(sample.weight, wavelength), depth)
+        else {
+            break;
+        };
+        throughput = survivor;
+        if !sample.is_delta {
+            skip_emission = gather;
+        }
+        bsdf_pdf = sample.pdf;
+        specular_bounce = sample.is_delta;
+        last_bounce = direct_only && !sample.is_delta;
+        ray = sample.ray;
+    }
+
+    radiance
+}
+
+// Randomly terminates a path after `depth` bounces, with a probability that grows as its
+// `throughput` falls. Returns the throughput of a surviving path, scaled up so the estimate
+// stays unbiased.
+pub fn russian_roulette(throughput: Color, depth: i32) -> Option<Color> {
+    if depth < MIN_DEPTH {
+        return Some(throughput);
+    }
+
+    let survival = throughput.x.max(throughput.y).max(throughput.z).min(0.95);
+    if get_random_f64() >= survival {
+        return None;
+    }
+    Some(throughput / survival)
+}
@@ -0,0 +1,21 @@
+pub mod aabb;
+pub mod animation;
+pub mod bdpt;
+pub mod camera;
+pub mod color;
+pub mod film;
+pub mod hittable;
+pub mod integrator;
+pub mod light;
+pub mod material;
+pub mod medium;
+pub mod microfacet;
+pub mod mlt;
+pub mod models;
+pub mod onb;
+pub mod photon;
+pub mod ray;
+pub mod scene;
+pub mod spectrum;
+pub mod utils;
+pub mod vec;
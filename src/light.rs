@@ -0,0 +1,904 @@
+use std::ops::RangeInclusive;
+
+use crate::color::Color;
+use crate::hittable::Hittable;
+use crate::material::emitted_radiance;
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::spectrum::upsample;
+use crate::utils::{get_random_f64, PI};
+use crate::vec::{Point3, Vec3};
+
+// Incident light arriving at a shading point from a sampled point on a light.
+pub struct LightSample {
+    // Unit direction from the shading point towards the light.
+    pub wi: Vec3,
+    pub radiance: Color,
+    // Distance to the sampled point, which shadow rays must reach unoccluded.
+    pub distance: f64,
+    // Solid angle density of `wi`. Delta lights report 1.0.
+    pub pdf: f64,
+    pub is_delta: bool,
+    // Surface normal at the sampled point. Zero for point-like and distant lights.
+    pub normal: Vec3,
+}
+
+// A ray of light leaving a light, for tracing paths from the lights.
+pub struct EmissionSample {
+    pub ray: Ray,
+    // Surface normal at the ray's origin. Zero for point-like lights.
+    pub normal: Vec3,
+    pub radiance: Color,
+    // Density of the origin per unit area. Point-like lights report 1.0.
+    pub pdf_pos: f64,
+    // Solid angle density of the ray's direction.
+    pub pdf_dir: f64,
+    // Whether the origin is a single point, which other paths can never reach.
+    pub is_delta: bool,
+}
+
+// A light that can be sampled from any point in the scene without a hit record. Lights that
+// aren't part of the world's geometry are invisible to intersection and don't cast shadows;
+// camera and BSDF rays see area lights through `hit_le`.
+//
+// In spectral mode `wavelength` is the wavelength the path follows, and returned radiances are
+// greys holding the value at that wavelength.
+pub trait Light: Send + Sync {
+    // Samples light arriving at `p` at `time`.
+    fn sample_li(&self, p: &Point3, wavelength: Option<f64>, time: f64) -> Option<LightSample>;
+
+    // Density with which `sample_li` picks `wi` from `p` at `time`. Always 0.0 for delta
+    // lights.
+    fn pdf_li(&self, _p: &Point3, _wi: &Vec3, _time: f64) -> f64 {
+        0.0
+    }
+
+    // Radiance seen by `ray` if it reaches the light within `ray_t`.
+    fn hit_le(
+        &self,
+        _ray: &Ray,
+        _ray_t: RangeInclusive<f64>,
+        _wavelength: Option<f64>,
+    ) -> Option<Color> {
+        None
+    }
+
+    // Samples a ray leaving the light at `time`. Lights that can't emit rays (such as
+    // directional lights, which would need the scene's bounds) return None.
+    fn sample_le(&self, _wavelength: Option<f64>, _time: f64) -> Option<EmissionSample> {
+        None
+    }
+
+    // Whether `sample_le` can emit rays from the light, so that photons and light subpaths
+    // carry its light.
+    fn can_emit_rays(&self) -> bool {
+        true
+    }
+
+    // Densities with which `sample_le` would pick the point `p`, where the light's normal is
+    // `normal`, and the direction `w` leaving it: (per unit area, per solid angle). Point-like
+    // lights report the same 1.0 for the point as `sample_le`.
+    fn pdf_le(&self, _p: &Point3, _normal: &Vec3, _w: &Vec3) -> (f64, f64) {
+        (0.0, 0.0)
+    }
+
+    // Distance along `ray` to the light's surface, and the normal there, if `hit_le` would see
+    // the light within `ray_t` at a finite distance.
+    fn hit_surface(&self, _ray: &Ray, _ray_t: RangeInclusive<f64>) -> Option<(f64, Vec3)> {
+        None
+    }
+}
+
+// An infinitesimal light shining equally in all directions.
+pub struct PointLight {
+    pub position: Point3,
+    pub intensity: Color,
+}
+
+impl Light for PointLight {
+    fn sample_li(&self, p: &Point3, wavelength: Option<f64>, _time: f64) -> Option<LightSample> {
+        let to_light = self.position - *p;
+        let distance_squared = to_light.length_squared();
+        let distance = distance_squared.sqrt();
+
+        Some(LightSample {
+            wi: to_light / distance,
+            radiance: upsample(self.intensity, wavelength) / distance_squared,
+            distance,
+            pdf: 1.0,
+            is_delta: true,
+            normal: Vec3::default(),
+        })
+    }
+
+    fn sample_le(&self, wavelength: Option<f64>, time: f64) -> Option<EmissionSample> {
+        Some(EmissionSample {
+            ray: Ray {
+                time,
+                ..Ray::new(self.position, Vec3::random_unit_vector())
+            },
+            normal: Vec3::default(),
+            radiance: upsample(self.intensity, wavelength),
+            pdf_pos: 1.0,
+            pdf_dir: 1.0 / (4.0 * PI),
+            is_delta: true,
+        })
+    }
+
+    // The position density matches `sample_le`'s, so light subpaths are weighted the same
+    // however they start.
+    fn pdf_le(&self, _p: &Point3, _normal: &Vec3, _w: &Vec3) -> (f64, f64) {
+        (1.0, 1.0 / (4.0 * PI))
+    }
+}
+
+// A point light restricted to a cone. The intensity is full within `inner_angle` of the axis and
+// falls off smoothly to zero at `outer_angle`.
+pub struct SpotLight {
+    position: Point3,
+    direction: Vec3,
+    intensity: Color,
+    cos_inner: f64,
+    cos_outer: f64,
+}
+
+impl SpotLight {
+    // NOTE: angles are in degrees, measured from the axis of the cone.
+    pub fn new(
+        position: Point3,
+        target: Point3,
+        intensity: Color,
+        inner_angle: f64,
+        outer_angle: f64,
+    ) -> SpotLight {
+        SpotLight {
+            position,
+            direction: (target - position).unit_vector(),
+            intensity,
+            cos_inner: inner_angle.to_radians().cos(),
+            cos_outer: outer_angle.to_radians().cos(),
+        }
+    }
+
+    fn falloff(&self, w: &Vec3) -> f64 {
+        let cos_theta = w.dot(&self.direction);
+        if cos_theta <= self.cos_outer {
+            return 0.0;
+        }
+        if cos_theta >= self.cos_inner {
+            return 1.0;
+        }
+        let t = (cos_theta - self.cos_outer) / (self.cos_inner - self.cos_outer);
+        t * t * (3.0 - 2.0 * t)
+    }
+
+    // Solid angle of the outer cone, which `sample_le` picks directions from uniformly.
+    fn cone_solid_angle(&self) -> f64 {
+        2.0 * PI * (1.0 - self.cos_outer)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample_li(&self, p: &Point3, wavelength: Option<f64>, _time: f64) -> Option<LightSample> {
+        let to_light = self.position - *p;
+        let distance_squared = to_light.length_squared();
+        let distance = distance_squared.sqrt();
+        let wi = to_light / distance;
+
+        let falloff = self.falloff(&-wi);
+        if falloff == 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            wi,
+            radiance: upsample(self.intensity, wavelength) * (falloff / distance_squared),
+            distance,
+            pdf: 1.0,
+            is_delta: true,
+            normal: Vec3::default(),
+        })
+    }
+
+    fn sample_le(&self, wavelength: Option<f64>, time: f64) -> Option<EmissionSample> {
+        let cos_theta = 1.0 - get_random_f64() * (1.0 - self.cos_outer);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let phi = 2.0 * PI * get_random_f64();
+        let direction = Onb::new(self.direction).local(Vec3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ));
+
+        Some(EmissionSample {
+            ray: Ray {
+                time,
+                ..Ray::new(self.position, direction)
+            },
+            normal: Vec3::default(),
+            radiance: upsample(self.intensity, wavelength) * self.falloff(&direction),
+            pdf_pos: 1.0,
+            pdf_dir: 1.0 / self.cone_solid_angle(),
+            is_delta: true,
+        })
+    }
+
+    fn pdf_le(&self, _p: &Point3, _normal: &Vec3, w: &Vec3) -> (f64, f64) {
+        if w.unit_vector().dot(&self.direction) < self.cos_outer {
+            return (1.0, 0.0);
+        }
+        (1.0, 1.0 / self.cone_solid_angle())
+    }
+}
+
+// A light infinitely far away, such as the sun. With a non-zero angular diameter the light
+// subtends a small disk in the sky and casts soft shadows.
+pub struct DirectionalLight {
+    // Direction the light travels in.
+    direction: Vec3,
+    // Irradiance on a surface facing the light.
+    irradiance: Color,
+    cos_theta_max: f64,
+}
+
+impl DirectionalLight {
+    // NOTE: `angular_diameter` is in degrees. The sun's is about 0.53.
+    pub fn new(direction: Vec3, irradiance: Color, angular_diameter: f64) -> DirectionalLight {
+        DirectionalLight {
+            direction: direction.unit_vector(),
+            irradiance,
+            cos_theta_max: (angular_diameter / 2.0).to_radians().cos(),
+        }
+    }
+
+    fn is_delta(&self) -> bool {
+        self.cos_theta_max >= 1.0
+    }
+
+    fn solid_angle(&self) -> f64 {
+        2.0 * PI * (1.0 - self.cos_theta_max)
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample_li(&self, _p: &Point3, wavelength: Option<f64>, _time: f64) -> Option<LightSample> {
+        let irradiance = upsample(self.irradiance, wavelength);
+
+        if self.is_delta() {
+            return Some(LightSample {
+                wi: -self.direction,
+                radiance: irradiance,
+                distance: f64::INFINITY,
+                pdf: 1.0,
+                is_delta: true,
+                normal: Vec3::default(),
+            });
+        }
+
+        let sin_theta_max = (1.0 - self.cos_theta_max * self.cos_theta_max).sqrt();
+        let wi = Onb::new(-self.direction).local(Vec3::random_to_sphere(sin_theta_max, 1.0));
+        let solid_angle = self.solid_angle();
+
+        Some(LightSample {
+            wi,
+            radiance: irradiance / solid_angle,
+            distance: f64::INFINITY,
+            pdf: 1.0 / solid_angle,
+            is_delta: false,
+            normal: Vec3::default(),
+        })
+    }
+
+    fn can_emit_rays(&self) -> bool {
+        false
+    }
+
+    fn pdf_li(&self, _p: &Point3, wi: &Vec3, _time: f64) -> f64 {
+        if self.is_delta() || wi.unit_vector().dot(&-self.direction) < self.cos_theta_max {
+            return 0.0;
+        }
+        1.0 / self.solid_angle()
+    }
+
+    fn hit_le(
+        &self,
+        ray: &Ray,
+        ray_t: RangeInclusive<f64>,
+        wavelength: Option<f64>,
+    ) -> Option<Color> {
+        if *ray_t.end() < f64::INFINITY
+            || self.is_delta()
+            || ray.direction.unit_vector().dot(&-self.direction) < self.cos_theta_max
+        {
+            return None;
+        }
+        Some(upsample(self.irradiance, wavelength) / self.solid_angle())
+    }
+}
+
+// A one-sided parallelogram light spanned by `u` and `v` from `corner`, emitting on the side
+// `u` x `v` points to.
+pub struct QuadLight {
+    corner: Point3,
+    u: Vec3,
+    v: Vec3,
+    radiance: Color,
+    normal: Vec3,
+    w: Vec3,
+    area: f64,
+}
+
+impl QuadLight {
+    pub fn new(corner: Point3, u: Vec3, v: Vec3, radiance: Color) -> QuadLight {
+        let n = u.cross(&v);
+        QuadLight {
+            corner,
+            u,
+            v,
+            radiance,
+            normal: n.unit_vector(),
+            w: n / n.dot(&n),
+            area: n.length(),
+        }
+    }
+
+    // Distance along `ray` to the quad, if it hits it from the emitting side.
+    fn intersect(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> Option<f64> {
+        let denom = self.normal.dot(&ray.direction);
+        if denom >= 0.0 {
+            return None;
+        }
+
+        let t = (self.corner - ray.origin).dot(&self.normal) / denom;
+        if !ray_t.contains(&t) {
+            return None;
+        }
+
+        let planar = ray.at(t) - self.corner;
+        let alpha = self.w.dot(&planar.cross(&self.v));
+        let beta = self.w.dot(&self.u.cross(&planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+        Some(t)
+    }
+}
+
+impl Light for QuadLight {
+    fn sample_li(&self, p: &Point3, wavelength: Option<f64>, _time: f64) -> Option<LightSample> {
+        let point = self.corner + self.u * get_random_f64() + self.v * get_random_f64();
+        area_light_sample(p, point, self.normal, self.area, self.radiance, wavelength)
+    }
+
+    fn pdf_li(&self, p: &Point3, wi: &Vec3, _time: f64) -> f64 {
+        let ray = Ray::new(*p, wi.unit_vector());
+        match self.intersect(&ray, 0.001..=f64::INFINITY) {
+            Some(t) => area_to_solid_angle(t, self.normal.dot(&ray.direction), self.area),
+            None => 0.0,
+        }
+    }
+
+    fn hit_le(
+        &self,
+        ray: &Ray,
+        ray_t: RangeInclusive<f64>,
+        wavelength: Option<f64>,
+    ) -> Option<Color> {
+        self.intersect(ray, ray_t)
+            .map(|_| upsample(self.radiance, wavelength))
+    }
+
+    fn sample_le(&self, wavelength: Option<f64>, time: f64) -> Option<EmissionSample> {
+        let point = self.corner + self.u * get_random_f64() + self.v * get_random_f64();
+        Some(area_emission_sample(
+            point,
+            self.normal,
+            self.area,
+            self.radiance,
+            wavelength,
+            time,
+        ))
+    }
+
+    fn pdf_le(&self, _p: &Point3, _normal: &Vec3, w: &Vec3) -> (f64, f64) {
+        area_emission_pdf(&self.normal, w, self.area)
+    }
+
+    fn hit_surface(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> Option<(f64, Vec3)> {
+        self.intersect(ray, ray_t).map(|t| (t, self.normal))
+    }
+}
+
+// A one-sided disk light facing along `normal`.
+pub struct DiskLight {
+    center: Point3,
+    radius: f64,
+    radiance: Color,
+    onb: Onb,
+}
+
+impl DiskLight {
+    pub fn new(center: Point3, normal: Vec3, radius: f64, radiance: Color) -> DiskLight {
+        DiskLight {
+            center,
+            radius,
+            radiance,
+            onb: Onb::new(normal.unit_vector()),
+        }
+    }
+
+    fn area(&self) -> f64 {
+        PI * self.radius * self.radius
+    }
+
+    fn intersect(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> Option<f64> {
+        let denom = self.onb.w.dot(&ray.direction);
+        if denom >= 0.0 {
+            return None;
+        }
+
+        let t = (self.center - ray.origin).dot(&self.onb.w) / denom;
+        if !ray_t.contains(&t) || (ray.at(t) - self.center).length_squared() > self.radius.powi(2) {
+            return None;
+        }
+        Some(t)
+    }
+}
+
+impl Light for DiskLight {
+    fn sample_li(&self, p: &Point3, wavelength: Option<f64>, _time: f64) -> Option<LightSample> {
+        let r = self.radius * get_random_f64().sqrt();
+        let phi = 2.0 * PI * get_random_f64();
+        let point = self.center + self.onb.local(Vec3::new(r * phi.cos(), r * phi.sin(), 0.0));
+        area_light_sample(p, point, self.onb.w, self.area(), self.radiance, wavelength)
+    }
+
+    fn sample_le(&self, wavelength: Option<f64>, time: f64) -> Option<EmissionSample> {
+        let r = self.radius * get_random_f64().sqrt();
+        let phi = 2.0 * PI * get_random_f64();
+        let point = self.center + self.onb.local(Vec3::new(r * phi.cos(), r * phi.sin(), 0.0));
+        Some(area_emission_sample(
+            point,
+            self.onb.w,
+            self.area(),
+            self.radiance,
+            wavelength,
+            time,
+        ))
+    }
+
+    fn pdf_le(&self, _p: &Point3, _normal: &Vec3, w: &Vec3) -> (f64, f64) {
+        area_emission_pdf(&self.onb.w, w, self.area())
+    }
+
+    fn pdf_li(&self, p: &Point3, wi: &Vec3, _time: f64) -> f64 {
+        let ray = Ray::new(*p, wi.unit_vector());
+        match self.intersect(&ray, 0.001..=f64::INFINITY) {
+            Some(t) => area_to_solid_angle(t, self.onb.w.dot(&ray.direction), self.area()),
+            None => 0.0,
+        }
+    }
+
+    fn hit_le(
+        &self,
+        ray: &Ray,
+        ray_t: RangeInclusive<f64>,
+        wavelength: Option<f64>,
+    ) -> Option<Color> {
+        self.intersect(ray, ray_t)
+            .map(|_| upsample(self.radiance, wavelength))
+    }
+
+    fn hit_surface(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> Option<(f64, Vec3)> {
+        self.intersect(ray, ray_t).map(|t| (t, self.onb.w))
+    }
+}
+
+// A spherical light emitting outwards from its surface.
+pub struct SphereLight {
+    pub center: Point3,
+    pub radius: f64,
+    pub radiance: Color,
+}
+
+impl SphereLight {
+    fn intersect(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> Option<f64> {
+        let oc = ray.origin - self.center;
+        let a = ray.direction.length_squared();
+        let half_b = ray.direction.dot(&oc);
+        let c = oc.dot(&oc) - self.radius * self.radius;
+
+        // Only the outside of the sphere emits
+        let discriminant = half_b * half_b - a * c;
+        if c <= 0.0 || discriminant < 0.0 {
+            return None;
+        }
+
+        let root = (-half_b - discriminant.sqrt()) / a;
+        ray_t.contains(&root).then_some(root)
+    }
+
+    fn solid_angle(&self, p: &Point3) -> f64 {
+        let distance_squared = (self.center - *p).length_squared();
+        let cos_theta_max = (1.0 - self.radius * self.radius / distance_squared)
+            .max(0.0)
+            .sqrt();
+        2.0 * PI * (1.0 - cos_theta_max)
+    }
+}
+
+impl Light for SphereLight {
+    fn sample_li(&self, p: &Point3, wavelength: Option<f64>, _time: f64) -> Option<LightSample> {
+        let to_center = self.center - *p;
+        let distance_squared = to_center.length_squared();
+        if distance_squared <= self.radius * self.radius {
+            return None;
+        }
+
+        let wi = Onb::new(to_center.unit_vector())
+            .local(Vec3::random_to_sphere(self.radius, distance_squared));
+        let distance = self.intersect(&Ray::new(*p, wi), 0.0..=f64::INFINITY)?;
+
+        Some(LightSample {
+            wi,
+            radiance: upsample(self.radiance, wavelength),
+            distance,
+            pdf: 1.0 / self.solid_angle(p),
+            is_delta: false,
+            normal: (*p + wi * distance - self.center) / self.radius,
+        })
+    }
+
+    fn pdf_li(&self, p: &Point3, wi: &Vec3, _time: f64) -> f64 {
+        match self.intersect(&Ray::new(*p, *wi), 0.001..=f64::INFINITY) {
+            Some(_) => 1.0 / self.solid_angle(p),
+            None => 0.0,
+        }
+    }
+
+    fn hit_le(
+        &self,
+        ray: &Ray,
+        ray_t: RangeInclusive<f64>,
+        wavelength: Option<f64>,
+    ) -> Option<Color> {
+        self.intersect(ray, ray_t)
+            .map(|_| upsample(self.radiance, wavelength))
+    }
+
+    fn sample_le(&self, wavelength: Option<f64>, time: f64) -> Option<EmissionSample> {
+        let normal = Vec3::random_unit_vector();
+        Some(area_emission_sample(
+            self.center + normal * self.radius,
+            normal,
+            4.0 * PI * self.radius * self.radius,
+            self.radiance,
+            wavelength,
+            time,
+        ))
+    }
+
+    fn pdf_le(&self, p: &Point3, _normal: &Vec3, w: &Vec3) -> (f64, f64) {
+        let normal = (*p - self.center) / self.radius;
+        area_emission_pdf(&normal, w, 4.0 * PI * self.radius * self.radius)
+    }
+
+    fn hit_surface(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> Option<(f64, Vec3)> {
+        self.intersect(ray, ray_t)
+            .map(|t| (t, (ray.at(t) - self.center) / self.radius))
+    }
+}
+
+// Samples the point `point` of a one-sided planar light facing along `normal`, as seen from `p`.
+fn area_light_sample(
+    p: &Point3,
+    point: Point3,
+    normal: Vec3,
+    area: f64,
+    radiance: Color,
+    wavelength: Option<f64>,
+) -> Option<LightSample> {
+    let to_light = point - *p;
+    let distance = to_light.length();
+    let wi = to_light / distance;
+
+    let cosine = normal.dot(&wi);
+    if cosine >= 0.0 {
+        return None;
+    }
+
+    Some(LightSample {
+        wi,
+        radiance: upsample(radiance, wavelength),
+        distance,
+        pdf: area_to_solid_angle(distance, cosine, area),
+        is_delta: false,
+        normal,
+    })
+}
+
+// Emits a ray at `time` from the point `point` of a one-sided light facing along `normal`, in a
+// cosine-distributed direction.
+fn area_emission_sample(
+    point: Point3,
+    normal: Vec3,
+    area: f64,
+    radiance: Color,
+    wavelength: Option<f64>,
+    time: f64,
+) -> EmissionSample {
+    let direction = Onb::new(normal).local(Vec3::random_cosine_direction());
+    EmissionSample {
+        ray: Ray {
+            time,
+            ..Ray::new(point, direction)
+        },
+        normal,
+        radiance: upsample(radiance, wavelength),
+        pdf_pos: 1.0 / area,
+        pdf_dir: direction.dot(&normal).max(0.0) / PI,
+        is_delta: false,
+    }
+}
+
+fn area_emission_pdf(normal: &Vec3, w: &Vec3, area: f64) -> (f64, f64) {
+    (1.0 / area, w.unit_vector().dot(normal).max(0.0) / PI)
+}
+
+// Converts the density of uniformly sampling a point on a surface of `area` into a solid angle
+// density, for a point `distance` away whose normal makes `cosine` with the direction to it.
+fn area_to_solid_angle(distance: f64, cosine: f64, area: f64) -> f64 {
+    distance * distance / (cosine.abs() * area)
+}
+
+// Samples an emissive hittable of the world as a light.
+struct HittableLight<'a> {
+    hittable: &'a dyn Hittable,
+    // Index of the hittable in the world.
+    object: usize,
+}
+
+impl Light for HittableLight<'_> {
+    fn sample_li(&self, p: &Point3, wavelength: Option<f64>, time: f64) -> Option<LightSample> {
+        let wi = self.hittable.random(p, time).unit_vector();
+        let pdf = self.hittable.pdf_value(p, &wi, time);
+
+        let ray = Ray {
+            time,
+            ..Ray::new(*p, wi)
+        };
+        let record = self.hittable.hit(&ray, 0.001..=f64::INFINITY)?;
+        let mat = record.mat.clone().unwrap();
+
+        Some(LightSample {
+            wi,
+            radiance: emitted_radiance(mat.as_ref(), &ray, &record, wavelength),
+            distance: record.t,
+            pdf,
+            is_delta: false,
+            normal: record.normal,
+        })
+    }
+
+    fn pdf_li(&self, p: &Point3, wi: &Vec3, time: f64) -> f64 {
+        self.hittable.pdf_value(p, wi, time)
+    }
+
+    // Hittables may emit from either side, so rays leave in a cosine-distributed direction on
+    // a randomly chosen side.
+    fn sample_le(&self, wavelength: Option<f64>, time: f64) -> Option<EmissionSample> {
+        let mut record = self.hittable.sample_surface(time)?;
+        if get_random_f64() < 0.5 {
+            record.normal = -record.normal;
+            record.front_face = false;
+        }
+        let direction = Onb::new(record.normal).local(Vec3::random_cosine_direction());
+
+        // The sampled point as seen by a ray arriving from one unit along `direction`.
+        record.t = 1.0;
+        let ray = Ray {
+            time,
+            ..Ray::new(record.p + direction, -direction)
+        };
+        let mat = record.mat.clone().unwrap();
+
+        Some(EmissionSample {
+            ray: ray.spawn(record.p, direction),
+            normal: record.normal,
+            radiance: emitted_radiance(mat.as_ref(), &ray, &record, wavelength),
+            pdf_pos: 1.0 / self.hittable.area(),
+            pdf_dir: 0.5 * direction.dot(&record.normal).max(0.0) / PI,
+            is_delta: false,
+        })
+    }
+
+    fn pdf_le(&self, _p: &Point3, normal: &Vec3, w: &Vec3) -> (f64, f64) {
+        (
+            1.0 / self.hittable.area(),
+            0.5 * w.unit_vector().dot(normal).abs() / PI,
+        )
+    }
+}
+
+// All the lights of a scene: its analytic lights, and its emissive hittables. Lights are picked
+// uniformly for next-event estimation.
+pub struct LightList<'a> {
+    hittables: Vec<HittableLight<'a>>,
+    lights: &'a [Box<dyn Light>],
+}
+
+impl<'a> LightList<'a> {
+    pub fn new(scene: &'a Scene) -> LightList<'a> {
+        let hittables = scene
+            .world
+            .iter()
+            .enumerate()
+            .filter(|(_, hittable)| hittable.is_emissive())
+            .map(|(object, hittable)| HittableLight {
+                hittable: hittable.as_ref(),
+                object,
+            })
+            .collect();
+        LightList {
+            hittables,
+            lights: &scene.lights,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.hittables.len() + self.lights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get(&self, index: usize) -> &dyn Light {
+        if index < self.hittables.len() {
+            &self.hittables[index]
+        } else {
+            self.lights[index - self.hittables.len()].as_ref()
+        }
+    }
+
+    // Picks a light uniformly, returning it along with the probability it was picked with.
+    pub fn choose(&self) -> Option<(&dyn Light, f64)> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let index = ((get_random_f64() * self.len() as f64) as usize).min(self.len() - 1);
+        Some((self.get(index), 1.0 / self.len() as f64))
+    }
+
+    // Samples a uniformly chosen light. The returned pdf accounts for the choice of light.
+    pub fn sample(&self, p: &Point3, wavelength: Option<f64>, time: f64) -> Option<LightSample> {
+        let (light, probability) = self.choose()?;
+        let mut sample = light.sample_li(p, wavelength, time)?;
+        sample.pdf *= probability;
+        Some(sample)
+    }
+
+    // The light sampling the emissive hittable at `object` in the world, if it is one.
+    pub fn object_light(&self, object: usize) -> Option<&dyn Light> {
+        self.hittables
+            .iter()
+            .find(|light| light.object == object)
+            .map(|light| light as &dyn Light)
+    }
+
+    // The scene's analytic lights, which aren't part of the world's geometry.
+    pub fn analytic(&self) -> &'a [Box<dyn Light>] {
+        self.lights
+    }
+
+    // Density of `sample` choosing `wi` from `p`, which is the average of the densities of the
+    // individual lights since any of them could have produced it. Zero without any lights.
+    pub fn pdf_value(&self, p: &Point3, wi: &Vec3, time: f64) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = (0..self.len())
+            .map(|i| self.get(i).pdf_li(p, wi, time))
+            .sum();
+        sum / self.len() as f64
+    }
+
+    // Total radiance of the analytic lights `ray` passes through within `ray_t`.
+    pub fn hit_le(
+        &self,
+        ray: &Ray,
+        ray_t: RangeInclusive<f64>,
+        wavelength: Option<f64>,
+    ) -> Option<Color> {
+        self.lights
+            .iter()
+            .filter_map(|light| light.hit_le(ray, ray_t.clone(), wavelength))
+            .reduce(|a, b| a + b)
+    }
+
+    // Like `hit_le`, but only from the analytic lights that can't emit rays, whose light
+    // photons don't carry.
+    pub fn hit_le_without_rays(
+        &self,
+        ray: &Ray,
+        ray_t: RangeInclusive<f64>,
+        wavelength: Option<f64>,
+    ) -> Option<Color> {
+        self.lights
+            .iter()
+            .filter(|light| !light.can_emit_rays())
+            .filter_map(|light| light.hit_le(ray, ray_t.clone(), wavelength))
+            .reduce(|a, b| a + b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_light_list_has_no_density() {
+        let scene = Scene::new(Vec::new());
+        let lights = LightList::new(&scene);
+        let p = Point3::default();
+
+        assert!(lights.is_empty());
+        assert!(lights.choose().is_none());
+        assert!(lights.sample(&p, None, 0.0).is_none());
+        assert_eq!(lights.pdf_value(&p, &Vec3::new(0.0, 1.0, 0.0), 0.0), 0.0);
+    }
+
+    #[test]
+    fn emissive_hittables_emit_from_their_front_side() {
+        use crate::hittable::{HittableList, Moving, Sphere};
+        use crate::material::DiffuseLight;
+        use crate::spectrum::Spectrum;
+        use std::sync::Arc;
+
+        let mesh: HittableList = vec![Box::new(Sphere::new(
+            Point3::default(),
+            1.0,
+            Arc::new(DiffuseLight::new(Spectrum::Constant(2.0))),
+        ))];
+        let scene = Scene::new(vec![Box::new(Moving::new(
+            Box::new(mesh),
+            Vec3::new(1.0, 0.0, 0.0),
+        ))]);
+        let lights = LightList::new(&scene);
+        assert_eq!(lights.len(), 1);
+
+        for i in 0..32 {
+            // Emission starts where the sphere is at the time it's sampled at.
+            let time = i as f64 / 31.0;
+            let emission = lights.get(0).sample_le(None, time).unwrap();
+            let center = Point3::new(time, 0.0, 0.0);
+            assert_eq!(emission.ray.time, time);
+            let outward = (emission.ray.origin - center).unit_vector();
+            let expected = if emission.normal.dot(&outward) > 0.0 {
+                2.0
+            } else {
+                0.0
+            };
+            assert!(((emission.ray.origin - center).length() - 1.0).abs() < 1e-9);
+            assert_eq!(emission.radiance.x, expected);
+        }
+    }
+
+    #[test]
+    fn point_light_emission_densities_agree() {
+        let light = PointLight {
+            position: Point3::new(0.0, 1.0, 0.0),
+            intensity: Color::new(1.0, 1.0, 1.0),
+        };
+        let emission = light.sample_le(None, 0.0).unwrap();
+        let (pdf_pos, pdf_dir) = light.pdf_le(
+            &emission.ray.origin,
+            &emission.normal,
+            &emission.ray.direction,
+        );
+        assert_eq!(pdf_pos, emission.pdf_pos);
+        assert_eq!(pdf_dir, emission.pdf_dir);
+    }
+}
@@ -1,23 +1,182 @@
-mod camera;
-mod color;
-mod hittable;
-mod material;
-mod models;
-mod ray;
-mod utils;
-mod vec;
-
 use std::sync::Arc;
 use std::time::Instant;
 
-use camera::Camera;
-use color::Color;
-use hittable::{HittableList, Sphere, Translation, Triangle};
-use material::{Dielectric, Lambertian, Material, Metal};
-use models::load_triangles;
-use vec::Point3;
+use raytracer::aabb::Aabb;
+use raytracer::animation::{Frame, Keyframes, Sequence, Turntable};
+use raytracer::bdpt::Bdpt;
+use raytracer::camera::{Camera, Projection};
+use raytracer::color::Color;
+use raytracer::hittable::{Hittable, HittableList, Sphere, Translation, Triangle};
+use raytracer::integrator::{
+    AmbientOcclusion, DebugView, DirectLighting, Integrator, NaivePathTracer, PathTracer,
+};
+use raytracer::light::SpotLight;
+use raytracer::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use raytracer::mlt::Metropolis;
+use raytracer::models::AnimatedModel;
+use raytracer::photon::PhotonMapping;
+use raytracer::scene::Scene;
+use raytracer::spectrum::Spectrum;
+use raytracer::vec::{Point3, Vec3};
 
 fn main() {
+    // `--ortho` anywhere swaps the perspective camera of framed models for an orthographic one,
+    // whose viewport height the framing picks.
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let projection = if args.iter().any(|arg| arg == "--ortho") {
+        args.retain(|arg| arg != "--ortho");
+        Projection::Orthographic { height: 1.0 }
+    } else {
+        Projection::Perspective
+    };
+    let mut args = args.into_iter().peekable();
+    // The light transport algorithm can be picked with the first argument.
+    let integrator_name = args.next();
+
+    // `turntable` as the second argument orbits a glTF model, duck.glb unless another is
+    // given as the third.
+    if args.next_if_eq("turntable").is_some() {
+        let filename = args.next().unwrap_or_else(|| "duck.glb".to_string());
+        let scene = model_scene(&filename);
+        let cam = Camera::new(16.0 / 9.0, 400).with_projection(projection);
+        let integrator = integrator(integrator_name.as_deref(), &scene, &cam);
+        Turntable {
+            frames: 36,
+            elevation: 20.0,
+        }
+        .render(&scene, &cam, integrator.as_ref());
+        return;
+    }
+
+    // `cornell` as the second argument renders a closed box lit only by a small light in its
+    // ceiling, which paths find by sampling it at every bounce.
+    if args.next_if_eq("cornell").is_some() {
+        let scene = cornell_scene();
+        let cam = Camera::new(1.0, 400)
+            .with_look_at(
+                Point3::new(0.0, 1.0, 3.8),
+                Point3::new(0.0, 1.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+            )
+            .with_vfov(40.0);
+        let integrator = integrator(integrator_name.as_deref(), &scene, &cam);
+        cam.render(&scene, integrator.as_ref());
+        return;
+    }
+
+    // `prism` as the second argument renders a glass prism splitting a beam of white light
+    // into a spectrum on a wall, following a wavelength per sample. Light only reaches the wall
+    // through the glass, so it takes the `photons` or `bdpt` integrator.
+    if args.next_if_eq("prism").is_some() {
+        let scene = prism_scene();
+        let cam = Camera::new(16.0 / 9.0, 400)
+            .with_look_at(
+                Point3::new(-2.0, 1.8, 3.5),
+                Point3::new(3.0, 0.5, -1.5),
+                Vec3::new(0.0, 1.0, 0.0),
+            )
+            .with_vfov(55.0)
+            .with_spectral(true);
+        let integrator = integrator(integrator_name.as_deref(), &scene, &cam);
+        cam.render(&scene, integrator.as_ref());
+        return;
+    }
+
+    // A glTF or OBJ model as the second argument is rendered on its own, seen from the front
+    // and above, with the camera moved back until it fills the frame. A number of frames after
+    // a glTF model plays its animation instead.
+    if let Some(filename) =
+        args.next_if(|arg| arg.ends_with(".glb") || arg.ends_with(".gltf") || arg.ends_with(".obj"))
+    {
+        let look_at = |bounds: &Aabb| {
+            Camera::new(16.0 / 9.0, 400)
+                .with_projection(projection)
+                .with_look_at(
+                    Point3::new(1.0, 0.5, 1.0),
+                    Point3::default(),
+                    Vec3::new(0.0, 1.0, 0.0),
+                )
+                .with_framing(bounds)
+        };
+
+        if let Some(frames) = args.next() {
+            if filename.ends_with(".obj") {
+                usage("Only glTF models can be animated");
+            }
+            let model = AnimatedModel::load(&filename);
+            let sequence = Sequence {
+                frames: frame_count(&frames),
+                frame_rate: 24.0,
+            };
+            // Framed once around every pose, so the camera holds still while the model moves.
+            let bounds = (0..sequence.frames)
+                .filter_map(|index| {
+                    let time = f64::from(index) / sequence.frame_rate;
+                    animated_scene(&model, time).world.bounding_box()
+                })
+                .reduce(|a, b| a.union(&b))
+                .expect("Nothing to frame");
+            sequence.render(|time| {
+                let scene = animated_scene(&model, time);
+                let camera = look_at(&bounds);
+                Frame {
+                    integrator: integrator(integrator_name.as_deref(), &scene, &camera),
+                    scene,
+                    camera,
+                }
+            });
+            return;
+        }
+
+        let scene = model_scene(&filename);
+        let cam = look_at(&scene.world.bounding_box().expect("Nothing to frame"));
+        let integrator = integrator(integrator_name.as_deref(), &scene, &cam);
+        cam.render(&scene, integrator.as_ref());
+        return;
+    }
+
+    // A number of frames as the second argument renders a fly-through instead of one image.
+    if let Some(frames) = args.next() {
+        let look_from = Keyframes::new(vec![
+            (0.0, Point3::new(0.0, 0.0, 0.0)),
+            (1.0, Point3::new(1.5, 0.5, -0.5)),
+            (2.0, Point3::new(2.0, 1.5, -2.0)),
+        ]);
+        Sequence {
+            frames: frame_count(&frames),
+            frame_rate: 24.0,
+        }
+        .render(|time| {
+            let scene = scene(time);
+            let camera = Camera::new(16.0 / 9.0, 400).with_look_at(
+                look_from.at(time),
+                Point3::new(0.0, 0.0, -2.0),
+                Vec3::new(0.0, 1.0, 0.0),
+            );
+            Frame {
+                integrator: integrator(integrator_name.as_deref(), &scene, &camera),
+                scene,
+                camera,
+            }
+        });
+        return;
+    }
+
+    let scene = scene(0.0);
+    let cam = Camera::new(16.0 / 9.0, 400);
+    let integrator = integrator(integrator_name.as_deref(), &scene, &cam);
+
+    let start = Instant::now();
+    cam.render(&scene, integrator.as_ref());
+    println!("Time to render: {}", start.elapsed().as_secs());
+}
+
+// The spheres scene `time` seconds into the fly-through, with the metal sphere on top bobbing
+// up and down and the yellow one polished over two seconds.
+fn scene(time: f64) -> Scene {
+    let bob = Keyframes::new(vec![(0.0, 1.0), (1.0, 1.4), (2.0, 1.0)]);
+    let fuzz = Keyframes::new(vec![(0.0, 1.0), (2.0, 0.0)]);
+
     // World
     let mut world = HittableList::new();
 
@@ -43,10 +202,11 @@ fn main() {
     });
     let fuzzy_metallic_yellow: Arc<dyn Material> = Arc::new(Metal {
         albedo: Color::new(0.8, 0.6, 0.2),
-        f: 1.0,
+        f: fuzz.at(time),
     });
     let glass: Arc<dyn Material> = Arc::new(Dielectric {
         refractive_index: 1.5,
+        dispersion: 0.0,
     });
 
     world.push(Box::new(Sphere::new(
@@ -60,7 +220,7 @@ fn main() {
         matte_pink.clone(),
     )));
     world.push(Box::new(Sphere::new(
-        Point3::new(0.0, 1.0, -2.0),
+        Point3::new(0.0, bob.at(time), -2.0),
         0.5,
         metallic_pink.clone(),
     )));
@@ -91,17 +251,226 @@ fn main() {
         .translate(Translation::Left(0.75)),
     ));
 
-    // 3D model rendering
-    /*
-    let duck = load_triangles("duck.glb");
-    println!("num triangles: {}", duck.len());
-    for triangle in duck {
-        world.push(triangle);
+    Scene::new(world)
+}
+
+// A Cornell box with red and green side walls, a white sphere and a glass one, lit by a small
+// square light under the ceiling. The box reaches behind the camera to keep out the sky.
+fn cornell_scene() -> Scene {
+    let mut world = HittableList::new();
+
+    let white: Arc<dyn Material> = Arc::new(Lambertian {
+        albedo: Color::new(0.73, 0.73, 0.73),
+    });
+    let red: Arc<dyn Material> = Arc::new(Lambertian {
+        albedo: Color::new(0.65, 0.05, 0.05),
+    });
+    let green: Arc<dyn Material> = Arc::new(Lambertian {
+        albedo: Color::new(0.12, 0.45, 0.15),
+    });
+    let glass: Arc<dyn Material> = Arc::new(Dielectric {
+        refractive_index: 1.5,
+        dispersion: 0.0,
+    });
+    let light: Arc<dyn Material> = Arc::new(DiffuseLight::new(Spectrum::Constant(15.0)));
+
+    // The box spans `x`, `y` and `z` from `origin`.
+    let origin = Point3::new(-1.0, 0.0, -1.0);
+    let x = Vec3::new(2.0, 0.0, 0.0);
+    let y = Vec3::new(0.0, 2.0, 0.0);
+    let z = Vec3::new(0.0, 0.0, 5.0);
+    quad(&mut world, origin, x, z, &white);
+    quad(&mut world, origin + y, x, z, &white);
+    quad(&mut world, origin, x, y, &white);
+    quad(&mut world, origin + z, x, y, &white);
+    quad(&mut world, origin, y, z, &red);
+    quad(&mut world, origin + x, y, z, &green);
+    // The light, facing down
+    quad(
+        &mut world,
+        Point3::new(-0.25, 1.99, -0.25),
+        Vec3::new(0.5, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 0.5),
+        &light,
+    );
+
+    world.push(Box::new(Sphere::new(
+        Point3::new(-0.45, 0.4, -0.3),
+        0.4,
+        white.clone(),
+    )));
+    world.push(Box::new(Sphere::new(
+        Point3::new(0.45, 0.35, 0.3),
+        0.35,
+        glass,
+    )));
+
+    Scene::new(world)
+}
+
+// A dense flint prism in a dark room, lit by a narrow spotlight at the angle of minimum
+// deviation, with a wall across the path of the light leaving it.
+fn prism_scene() -> Scene {
+    let mut world = HittableList::new();
+
+    let white: Arc<dyn Material> = Arc::new(Lambertian {
+        albedo: Color::new(0.8, 0.8, 0.8),
+    });
+    let black: Arc<dyn Material> = Arc::new(Lambertian {
+        albedo: Color::new(0.05, 0.05, 0.05),
+    });
+    let flint: Arc<dyn Material> = Arc::new(Dielectric {
+        refractive_index: 1.6,
+        dispersion: 0.03,
+    });
+    let dim_light: Arc<dyn Material> = Arc::new(DiffuseLight::new(Spectrum::Constant(0.3)));
+
+    // The room, shutting out the sky, with a dim light in the ceiling
+    world.push(Box::new(Sphere::new(Point3::default(), 50.0, black)));
+    quad(
+        &mut world,
+        Point3::new(-3.0, 5.0, -3.0),
+        Vec3::new(6.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 6.0),
+        &dim_light,
+    );
+
+    // Floor, and the wall 8 units from the prism
+    quad(
+        &mut world,
+        Point3::new(-10.0, 0.0, 10.0),
+        Vec3::new(20.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -20.0),
+        &white,
+    );
+    quad(
+        &mut world,
+        Point3::new(5.64, 0.0, -7.6),
+        Vec3::new(3.95, 0.0, 9.19),
+        Vec3::new(0.0, 3.0, 0.0),
+        &white,
+    );
+
+    // An equilateral prism standing on the floor, wound to face outwards
+    let corners: Vec<Point3> = (0..3)
+        .map(|i| {
+            let angle = (90.0 + 120.0 * f64::from(i)).to_radians();
+            Point3::new(0.6 * angle.cos(), 0.0, 0.6 * angle.sin())
+        })
+        .collect();
+    let up = Vec3::new(0.0, 1.2, 0.0);
+    let mut prism = HittableList::new();
+    prism.push(Box::new(Triangle::new(
+        corners[0],
+        corners[1],
+        corners[2],
+        flint.clone(),
+    )));
+    prism.push(Box::new(Triangle::new(
+        corners[0] + up,
+        corners[2] + up,
+        corners[1] + up,
+        flint.clone(),
+    )));
+    for i in 0..3 {
+        let (a, b) = (corners[i], corners[(i + 1) % 3]);
+        quad(&mut prism, a, up, b - a, &flint);
     }
-    */
-    let cam = Camera::new(16.0 / 9.0, 400);
+    world.push(Box::new(prism));
 
-    let start = Instant::now();
-    cam.render(&world);
-    println!("Time to render: {}", start.elapsed().as_secs());
+    let mut scene = Scene::new(world);
+    scene.lights.push(Box::new(SpotLight::new(
+        Point3::new(-4.86, 0.6, -1.8),
+        Point3::new(-0.26, 0.6, 0.15),
+        Color::new(2000.0, 2000.0, 2000.0),
+        0.3,
+        0.5,
+    )));
+    scene
+}
+
+// Adds the parallelogram with a corner at `corner` and sides `u` and `v` as two triangles,
+// facing along the cross product of `u` and `v`.
+fn quad(world: &mut HittableList, corner: Point3, u: Vec3, v: Vec3, mat: &Arc<dyn Material>) {
+    let (a, b, c, d) = (corner, corner + u, corner + u + v, corner + v);
+    world.push(Box::new(Triangle::new(a, b, c, mat.clone())));
+    world.push(Box::new(Triangle::new(a, c, d, mat.clone())));
+}
+
+// A glTF or OBJ model on its own, lit by the sky.
+fn model_scene(filename: &str) -> Scene {
+    let mut world = HittableList::new();
+    let triangles = if filename.ends_with(".obj") {
+        raytracer::models::load_obj(filename).unwrap_or_else(|error| {
+            eprintln!("Failed to load {filename}: {error}");
+            std::process::exit(1);
+        })
+    } else {
+        raytracer::models::load_triangles(filename)
+    };
+    println!("num triangles: {}", triangles.len());
+    for triangle in triangles {
+        world.push(Box::new(triangle));
+    }
+    Scene::new(world)
+}
+
+// The pose of an animated glTF model `time` seconds in, lit by the sky.
+fn animated_scene(model: &AnimatedModel, time: f64) -> Scene {
+    let mut world = HittableList::new();
+    for triangle in model.triangles_at(time) {
+        world.push(Box::new(triangle));
+    }
+    Scene::new(world)
+}
+
+// Parses a frame count argument, or explains how to run the renderer and exits.
+fn frame_count(arg: &str) -> u32 {
+    arg.parse()
+        .unwrap_or_else(|_| usage(&format!("Expected a number of frames, got `{arg}`")))
+}
+
+// Reports a mistake in the arguments, explains how to run the renderer and exits.
+fn usage(message: &str) -> ! {
+    eprintln!("{message}");
+    eprintln!(
+        "Usage: raytracer [--ortho] [integrator] [frames | cornell | prism | model.glb [frames] | model.obj | turntable [model]]"
+    );
+    eprintln!(
+        "Integrators: pt (the default), naive, direct, bdpt, photons, mlt, ao, normals, depth, uv, material, object, cost"
+    );
+    std::process::exit(2);
+}
+
+fn integrator(name: Option<&str>, scene: &Scene, cam: &Camera) -> Box<dyn Integrator> {
+    match name {
+        None | Some("pt") => Box::new(PathTracer),
+        Some("naive") => Box::new(NaivePathTracer),
+        Some("direct") => Box::new(DirectLighting),
+        Some("bdpt") => Box::new(Bdpt),
+        Some("mlt") => Box::new(Metropolis {
+            bootstrap_samples: 100_000,
+            chains: 1000,
+            large_step_probability: 0.3,
+            sigma: 0.01,
+        }),
+        Some("photons") => Box::new(PhotonMapping::new(
+            scene,
+            500_000,
+            0.05,
+            cam.shutter(),
+            cam.is_spectral(),
+        )),
+        Some("ao") => Box::new(AmbientOcclusion {
+            samples: 16,
+            radius: 1.0,
+        }),
+        Some("normals") => Box::new(DebugView::Normals),
+        Some("depth") => Box::new(DebugView::Depth { max_distance: 10.0 }),
+        Some("uv") => Box::new(DebugView::Uv),
+        Some("material") => Box::new(DebugView::materials(scene)),
+        Some("object") => Box::new(DebugView::Object),
+        Some("cost") => Box::new(DebugView::Cost { max_tests: 20 }),
+        Some(name) => usage(&format!("Unknown integrator `{name}`")),
+    }
 }
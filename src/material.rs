@@ -1,11 +1,59 @@
 use crate::color::Color;
 use crate::hittable::HitRecord;
+use crate::microfacet::{fresnel_conductor, fresnel_dielectric, refract, Ggx};
+use crate::onb::Onb;
 use crate::ray::Ray;
-use crate::utils::get_random_f64;
+use crate::spectrum::{rgb_to_spectral, Spectrum};
+use crate::utils::{get_random_f64, PI};
 use crate::vec::Vec3;
 
+// A direction sampled from a material, along with what an integrator needs to weight it.
+pub struct BsdfSample {
+    pub ray: Ray,
+    // The BSDF times the cosine of the scattered direction, divided by `pdf`.
+    pub weight: Color,
+    // Solid angle density of the scattered direction. Meaningless for delta lobes.
+    pub pdf: f64,
+    // Whether the direction came from a delta lobe (a perfect mirror or refraction), which
+    // `eval` and `pdf` can't represent.
+    pub is_delta: bool,
+}
+
+// Materials are described by their BSDF. `wo` and `wi` are unit vectors pointing away from the
+// hit point, `wo` towards the viewer and `wi` towards the light. Materials that only have delta
+// lobes need only implement `sample`.
 pub trait Material: Send + Sync {
-    fn scatter(&self, ray_in: &Ray, record: HitRecord) -> Option<(Color, Ray)>;
+    // Evaluates the BSDF times the absolute cosine of `wi`.
+    fn eval(&self, _wo: &Vec3, _wi: &Vec3, _record: &HitRecord) -> Color {
+        Color::default()
+    }
+
+    fn sample(&self, ray_in: &Ray, record: &HitRecord) -> Option<BsdfSample>;
+
+    // Density with which `sample` would choose `wi`.
+    fn pdf(&self, _wo: &Vec3, _wi: &Vec3, _record: &HitRecord) -> f64 {
+        0.0
+    }
+
+    // Adapter for callers that only need an attenuation and a scattered ray.
+    fn scatter(&self, ray_in: &Ray, record: HitRecord) -> Option<(Color, Ray)> {
+        self.sample(ray_in, &record)
+            .map(|sample| (sample.weight, sample.ray))
+    }
+
+    fn emitted(&self, _ray_in: &Ray, _record: &HitRecord) -> Color {
+        Color::default()
+    }
+
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    // Emitted radiance at a single wavelength, used by spectral rendering. Defaults to
+    // upsampling the RGB emission.
+    fn emitted_at(&self, ray_in: &Ray, record: &HitRecord, wavelength: f64) -> f64 {
+        rgb_to_spectral(self.emitted(ray_in, record), wavelength)
+    }
 }
 
 pub struct Lambertian {
@@ -13,7 +61,11 @@ pub struct Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _ray_in: &Ray, record: HitRecord) -> Option<(Color, Ray)> {
+    fn eval(&self, _wo: &Vec3, wi: &Vec3, record: &HitRecord) -> Color {
+        self.albedo * (wi.dot(&record.normal).max(0.0) / PI)
+    }
+
+    fn sample(&self, ray_in: &Ray, record: &HitRecord) -> Option<BsdfSample> {
         let mut scatter_direction = record.normal + Vec3::random_unit_vector();
 
         // Catch degenerate scatter direction
@@ -21,7 +73,17 @@ impl Material for Lambertian {
             scatter_direction = record.normal;
         }
 
-        Some((self.albedo, Ray::new(record.p, scatter_direction)))
+        let wi = scatter_direction.unit_vector();
+        Some(BsdfSample {
+            ray: ray_in.spawn(record.p, scatter_direction),
+            weight: self.albedo,
+            pdf: wi.dot(&record.normal).max(0.0) / PI,
+            is_delta: false,
+        })
+    }
+
+    fn pdf(&self, _wo: &Vec3, wi: &Vec3, record: &HitRecord) -> f64 {
+        wi.dot(&record.normal).max(0.0) / PI
     }
 }
 
@@ -30,49 +92,774 @@ pub struct Metal {
     pub f: f64, // fuzz factor
 }
 
+impl Metal {
+    // Density of the fuzzed reflection picking `wi`, before directions below the surface are
+    // discarded.
+    fn fuzz_pdf(&self, wo: &Vec3, wi: &Vec3, record: &HitRecord) -> f64 {
+        fuzz_density(&(-*wo).reflect(&record.normal), self.f, wi)
+    }
+}
+
+// Samples are the mirror direction pushed to a random point on a sphere of radius `f` around
+// it. A perfect mirror (`f` = 0) is a delta lobe; fuzzy ones have the density of
+// `fuzz_density`, and absorb the samples that end up below the surface.
 impl Material for Metal {
-    fn scatter(&self, ray_in: &Ray, record: HitRecord) -> Option<(Color, Ray)> {
-        let reflected = ray_in.direction.reflect(&record.normal);
+    fn eval(&self, wo: &Vec3, wi: &Vec3, record: &HitRecord) -> Color {
+        if self.f == 0.0 || wi.dot(&record.normal) <= 0.0 {
+            return Color::default();
+        }
+        self.albedo * self.fuzz_pdf(wo, wi, record)
+    }
+
+    fn sample(&self, ray_in: &Ray, record: &HitRecord) -> Option<BsdfSample> {
+        let wo = -ray_in.direction.unit_vector();
+        let reflected = (-wo).reflect(&record.normal);
+
+        let scattered = ray_in.spawn(record.p, reflected + Vec3::random_unit_vector() * self.f);
+        if scattered.direction.dot(&record.normal) <= 0.0 {
+            return None;
+        }
 
-        let scattered = Ray::new(record.p, reflected + Vec3::random_unit_vector() * self.f);
-        if scattered.direction.dot(&record.normal) > 0.0 {
-            Some((self.albedo, scattered))
+        let is_delta = self.f == 0.0;
+        let pdf = if is_delta {
+            0.0
         } else {
-            None
+            self.fuzz_pdf(&wo, &scattered.direction.unit_vector(), record)
+        };
+        Some(BsdfSample {
+            ray: scattered,
+            weight: self.albedo,
+            pdf,
+            is_delta,
+        })
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, record: &HitRecord) -> f64 {
+        if self.f == 0.0 || wi.dot(&record.normal) <= 0.0 {
+            return 0.0;
         }
+        self.fuzz_pdf(wo, wi, record)
+    }
+}
+
+// Solid angle density of the unit direction `wi` when directions are picked as the unit vector
+// `center` plus a point uniformly distributed over a sphere of radius `radius`. Each point of
+// that sphere along `wi`, at distance t with a normal making cosine c with `wi`, contributes
+// t² / (4π radius² c).
+fn fuzz_density(center: &Vec3, radius: f64, wi: &Vec3) -> f64 {
+    let b = wi.dot(center);
+    let discriminant = b * b - 1.0 + radius * radius;
+    if discriminant <= 0.0 {
+        return 0.0;
+    }
+
+    let root = discriminant.sqrt();
+    let distances_squared: f64 = [b - root, b + root]
+        .into_iter()
+        .filter(|&t| t > 0.0)
+        .map(|t| t * t)
+        .sum();
+    distances_squared / (4.0 * PI * radius * root)
+}
+
+// A rough metal, modelled with the GGX microfacet distribution and the exact Fresnel equations
+// for a complex index of refraction.
+pub struct Conductor {
+    pub eta: Color,
+    pub k: Color,
+    pub roughness: f64,
+}
+
+impl Conductor {
+    pub fn gold(roughness: f64) -> Conductor {
+        Conductor {
+            eta: Color::new(0.143, 0.375, 1.442),
+            k: Color::new(3.983, 2.386, 1.603),
+            roughness,
+        }
+    }
+
+    pub fn copper(roughness: f64) -> Conductor {
+        Conductor {
+            eta: Color::new(0.200, 0.924, 1.102),
+            k: Color::new(3.913, 2.453, 2.142),
+            roughness,
+        }
+    }
+
+    pub fn aluminium(roughness: f64) -> Conductor {
+        Conductor {
+            eta: Color::new(1.657, 0.880, 0.521),
+            k: Color::new(9.224, 6.270, 4.837),
+            roughness,
+        }
+    }
+}
+
+impl Material for Conductor {
+    fn eval(&self, wo: &Vec3, wi: &Vec3, record: &HitRecord) -> Color {
+        let onb = Onb::new(record.normal);
+        let wo = onb.to_local(*wo);
+        let wi = onb.to_local(*wi);
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Color::default();
+        }
+
+        let ggx = Ggx::new(self.roughness);
+        let h = (wo + wi).unit_vector();
+        fresnel_conductor(wo.dot(&h), self.eta, self.k) * (ggx.d(h) * ggx.g2(wo, wi) / (4.0 * wo.z))
+    }
+
+    fn sample(&self, ray_in: &Ray, record: &HitRecord) -> Option<BsdfSample> {
+        let onb = Onb::new(record.normal);
+        let wo = onb.to_local(-ray_in.direction.unit_vector());
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let ggx = Ggx::new(self.roughness);
+        let m = ggx.sample_visible_normal(wo, get_random_f64(), get_random_f64());
+        let wi = (-wo).reflect(&m);
+        if wi.z <= 0.0 {
+            return None;
+        }
+
+        // With visible normal sampling, D and most of the geometry term cancel against the pdf.
+        let weight =
+            fresnel_conductor(wo.dot(&m), self.eta, self.k) * (ggx.g2(wo, wi) / ggx.g1(wo));
+
+        Some(BsdfSample {
+            ray: ray_in.spawn(record.p, onb.local(wi)),
+            weight,
+            pdf: ggx.visible_normal_pdf(wo, m) / (4.0 * wo.dot(&m)),
+            is_delta: false,
+        })
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, record: &HitRecord) -> f64 {
+        let onb = Onb::new(record.normal);
+        let wo = onb.to_local(*wo);
+        let wi = onb.to_local(*wi);
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+
+        let h = (wo + wi).unit_vector();
+        Ggx::new(self.roughness).visible_normal_pdf(wo, h) / (4.0 * wo.dot(&h))
     }
 }
 
+// Wavelengths (in nanometres) representative of the red, green and blue channels of `Color`.
+const RGB_WAVELENGTHS: [f64; 3] = [610.0, 550.0, 465.0];
+
+// The Fraunhofer d-line, at which `Dielectric::refractive_index` is specified.
+const REFERENCE_WAVELENGTH: f64 = 587.6;
+
 pub struct Dielectric {
     pub refractive_index: f64,
+    // Cauchy B coefficient in µm². 0.0 disables dispersion; crown glass is around 0.0042 and
+    // diamond around 0.0135.
+    pub dispersion: f64,
+}
+
+impl Dielectric {
+    // Refractive index at `wavelength` (in nanometres) following Cauchy's equation
+    // n = A + B / λ², with A chosen so that the index at the d-line is `refractive_index`.
+    pub fn refractive_index_at(&self, wavelength: f64) -> f64 {
+        let inverse_square = |nm: f64| 1.0e6 / (nm * nm);
+        self.refractive_index
+            + self.dispersion * (inverse_square(wavelength) - inverse_square(REFERENCE_WAVELENGTH))
+    }
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray_in: &Ray, record: HitRecord) -> Option<(Color, Ray)> {
-        let attenuation = Color::new(1.0, 1.0, 1.0);
+    fn sample(&self, ray_in: &Ray, record: &HitRecord) -> Option<BsdfSample> {
+        let mut attenuation = Color::new(1.0, 1.0, 1.0);
+        let mut wavelength = ray_in.wavelength;
+
+        // A dispersive interface bends every wavelength differently, so a path that is not yet
+        // restricted to a single wavelength picks one of the colour channels to follow.
+        if self.dispersion != 0.0 && wavelength.is_none() {
+            let channel = ((get_random_f64() * 3.0) as usize).min(2);
+            attenuation = Color::default();
+            match channel {
+                0 => attenuation.x = 3.0,
+                1 => attenuation.y = 3.0,
+                _ => attenuation.z = 3.0,
+            }
+            wavelength = Some(RGB_WAVELENGTHS[channel]);
+        }
+
+        let refractive_index = match wavelength {
+            Some(wavelength) if self.dispersion != 0.0 => self.refractive_index_at(wavelength),
+            _ => self.refractive_index,
+        };
 
         let refraction_ratio = if record.front_face {
-            1.0 / self.refractive_index
+            1.0 / refractive_index
         } else {
-            self.refractive_index
+            refractive_index
         };
 
         let unit_direction = ray_in.direction.unit_vector();
-        let cos_theta = unit_direction.dot(&record.normal).min(1.0);
+        let cos_theta = (-unit_direction).dot(&record.normal).min(1.0);
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
+        // Reflects when refraction is impossible, and otherwise with the Fresnel reflectance.
         let new_ray_direction = if refraction_ratio * sin_theta > 1.0
-        //|| reflectance(cos_theta, refraction_ratio) > get_random_f64()
+            || reflectance(cos_theta, refraction_ratio) > get_random_f64()
         {
             unit_direction.reflect(&record.normal)
         } else {
             unit_direction.refract(&record.normal, refraction_ratio)
         };
 
-        Some((attenuation, Ray::new(record.p, new_ray_direction)))
+        let mut scattered = ray_in.spawn(record.p, new_ray_direction);
+        scattered.wavelength = wavelength;
+
+        Some(BsdfSample {
+            ray: scattered,
+            weight: attenuation,
+            pdf: 0.0,
+            is_delta: true,
+        })
+    }
+}
+
+// Frosted glass: a dielectric interface whose microfacets follow the GGX distribution, so both
+// reflection and transmission are blurred by `roughness`.
+pub struct RoughDielectric {
+    pub refractive_index: f64,
+    pub roughness: f64,
+}
+
+impl RoughDielectric {
+    // Ratio of the refractive index across the interface to the one on the side of `wo`.
+    fn eta(&self, front_face: bool) -> f64 {
+        if front_face {
+            self.refractive_index
+        } else {
+            1.0 / self.refractive_index
+        }
+    }
+}
+
+impl Material for RoughDielectric {
+    fn eval(&self, wo: &Vec3, wi: &Vec3, record: &HitRecord) -> Color {
+        let onb = Onb::new(record.normal);
+        let wo = onb.to_local(*wo);
+        let wi = onb.to_local(*wi);
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return Color::default();
+        }
+
+        let eta = self.eta(record.front_face);
+        let ggx = Ggx::new(self.roughness);
+
+        let f = if wi.z > 0.0 {
+            let h = (wo + wi).unit_vector();
+            fresnel_dielectric(wo.dot(&h), eta) * ggx.d(h) * ggx.g2(wo, wi) / (4.0 * wo.z)
+        } else {
+            let Some(wm) = transmission_half_vector(wo, wi, eta) else {
+                return Color::default();
+            };
+            let denom = wi.dot(&wm) + wo.dot(&wm) / eta;
+            (1.0 - fresnel_dielectric(wo.dot(&wm), eta))
+                * ggx.d(wm)
+                * ggx.g2(wo, wi)
+                * (wi.dot(&wm) * wo.dot(&wm) / (wo.z * denom * denom)).abs()
+        };
+        Color::new(f, f, f)
+    }
+
+    fn sample(&self, ray_in: &Ray, record: &HitRecord) -> Option<BsdfSample> {
+        let onb = Onb::new(record.normal);
+        let wo = onb.to_local(-ray_in.direction.unit_vector());
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let eta = self.eta(record.front_face);
+
+        let ggx = Ggx::new(self.roughness);
+        let m = ggx.sample_visible_normal(wo, get_random_f64(), get_random_f64());
+        let reflectance = fresnel_dielectric(wo.dot(&m), eta);
+
+        // Choose between reflection and transmission in proportion to the Fresnel term, which
+        // then cancels out of the weight.
+        let wi = if get_random_f64() < reflectance {
+            let wi = (-wo).reflect(&m);
+            if wi.z <= 0.0 {
+                return None;
+            }
+            wi
+        } else {
+            let wi = refract(wo, m, eta)?;
+            if wi.z >= 0.0 {
+                return None;
+            }
+            wi
+        };
+
+        let g = ggx.g2(wo, wi) / ggx.g1(wo);
+        let wi = onb.local(wi);
+        Some(BsdfSample {
+            ray: ray_in.spawn(record.p, wi),
+            weight: Color::new(g, g, g),
+            pdf: self.pdf(&onb.local(wo), &wi, record),
+            is_delta: false,
+        })
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, record: &HitRecord) -> f64 {
+        let onb = Onb::new(record.normal);
+        let wo = onb.to_local(*wo);
+        let wi = onb.to_local(*wi);
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return 0.0;
+        }
+
+        let eta = self.eta(record.front_face);
+        let ggx = Ggx::new(self.roughness);
+
+        if wi.z > 0.0 {
+            let h = (wo + wi).unit_vector();
+            fresnel_dielectric(wo.dot(&h), eta) * ggx.visible_normal_pdf(wo, h) / (4.0 * wo.dot(&h))
+        } else {
+            let Some(wm) = transmission_half_vector(wo, wi, eta) else {
+                return 0.0;
+            };
+            let denom = wi.dot(&wm) + wo.dot(&wm) / eta;
+            (1.0 - fresnel_dielectric(wo.dot(&wm), eta))
+                * ggx.visible_normal_pdf(wo, wm)
+                * wi.dot(&wm).abs()
+                / (denom * denom)
+        }
     }
 }
 
+// A Disney-style principled BSDF. A single set of artist-friendly parameters blends a diffuse
+// base (with sheen and a subsurface approximation), a GGX specular lobe, a clearcoat layer and
+// rough transmission. All parameters are in [0, 1].
+pub struct Principled {
+    pub base_color: Color,
+    pub metallic: f64,
+    pub roughness: f64,
+    pub specular: f64,
+    pub specular_tint: f64,
+    pub sheen: f64,
+    pub clearcoat: f64,
+    pub transmission: f64,
+    pub subsurface: f64,
+    // Stretches highlights along the hit's tangent, the direction of increasing `u`.
+    pub anisotropic: f64,
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        Principled {
+            base_color: Color::new(0.8, 0.8, 0.8),
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            specular_tint: 0.0,
+            sheen: 0.0,
+            clearcoat: 0.0,
+            transmission: 0.0,
+            subsurface: 0.0,
+            anisotropic: 0.0,
+        }
+    }
+}
+
+impl Principled {
+    // Relative probabilities of sampling the diffuse, specular, clearcoat and transmission
+    // lobes.
+    fn lobe_probabilities(&self) -> [f64; 4] {
+        let diffuse = (1.0 - self.metallic) * (1.0 - self.transmission);
+        let transmission = (1.0 - self.metallic) * self.transmission;
+        let clearcoat = 0.25 * self.clearcoat;
+        let total = diffuse + 1.0 + clearcoat + transmission;
+        [
+            diffuse / total,
+            1.0 / total,
+            clearcoat / total,
+            transmission / total,
+        ]
+    }
+
+    fn specular_distribution(&self) -> Ggx {
+        Ggx::anisotropic(self.roughness, self.anisotropic)
+    }
+
+    fn clearcoat_distribution() -> Ggx {
+        Ggx {
+            alpha_x: 0.05,
+            alpha_y: 0.05,
+        }
+    }
+
+    // The base colour normalised by its luminance, used to tint the specular and sheen lobes.
+    fn tint(&self) -> Color {
+        let luminance = self.base_color.luminance();
+        if luminance > 0.0 {
+            self.base_color / luminance
+        } else {
+            Color::new(1.0, 1.0, 1.0)
+        }
+    }
+
+    // Refractive index implied by the `specular` parameter, where 0.5 corresponds to 1.5.
+    fn eta(&self, front_face: bool) -> f64 {
+        let sqrt_f0 = (0.08 * self.specular).sqrt().min(0.99);
+        let ior = (1.0 + sqrt_f0) / (1.0 - sqrt_f0);
+        if front_face {
+            ior
+        } else {
+            1.0 / ior
+        }
+    }
+
+    // Evaluates the BSDF times the cosine of `wi`. Both directions are in the local shading
+    // frame, with `wo` in the upper hemisphere.
+    fn eval_local(&self, wo: Vec3, wi: Vec3, eta: f64) -> Color {
+        let white = Color::new(1.0, 1.0, 1.0);
+        let cos_o = wo.z;
+        let cos_i = wi.z;
+
+        if cos_i < 0.0 {
+            let transmission = (1.0 - self.metallic) * self.transmission;
+            let Some(wm) = transmission_half_vector(wo, wi, eta) else {
+                return Color::default();
+            };
+            let ggx = self.specular_distribution();
+            let fresnel = fresnel_dielectric(wo.dot(&wm), eta);
+            let denom = wi.dot(&wm) + wo.dot(&wm) / eta;
+            let ft = transmission
+                * (1.0 - fresnel)
+                * ggx.d(wm)
+                * ggx.g2(wo, wi)
+                * (wi.dot(&wm) * wo.dot(&wm) / (cos_i * cos_o * denom * denom)).abs();
+            return self.base_color * (ft * -cos_i);
+        }
+
+        let h = (wo + wi).unit_vector();
+        let cos_d = wi.dot(&h);
+        let fl = schlick_weight(cos_i);
+        let fv = schlick_weight(cos_o);
+        let fh = schlick_weight(cos_d);
+
+        // Burley diffuse with retro-reflection, blended with the Hanrahan-Krueger based
+        // subsurface approximation.
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let fd = (1.0 + (fd90 - 1.0) * fl) * (1.0 + (fd90 - 1.0) * fv);
+        let fss90 = self.roughness * cos_d * cos_d;
+        let fss = (1.0 + (fss90 - 1.0) * fl) * (1.0 + (fss90 - 1.0) * fv);
+        let ss = 1.25 * (fss * (1.0 / (cos_i + cos_o) - 0.5) + 0.5);
+        let sheen_color = (white + self.tint()) * 0.5;
+        let diffuse = (self.base_color * ((fd + (ss - fd) * self.subsurface) / PI)
+            + sheen_color * (self.sheen * fh))
+            * ((1.0 - self.metallic) * (1.0 - self.transmission));
+
+        let spec_tint = white * (1.0 - self.specular_tint) + self.tint() * self.specular_tint;
+        let spec_f0 = spec_tint * (0.08 * self.specular * (1.0 - self.metallic))
+            + self.base_color * self.metallic;
+        let fresnel = spec_f0 + (white - spec_f0) * fh;
+        let ggx = self.specular_distribution();
+        let specular = fresnel * (ggx.d(h) * ggx.g2(wo, wi) / (4.0 * cos_i * cos_o));
+
+        let cc = Self::clearcoat_distribution();
+        let clearcoat = 0.25 * self.clearcoat * (0.04 + 0.96 * fh) * cc.d(h) * cc.g2(wo, wi)
+            / (4.0 * cos_i * cos_o);
+
+        (diffuse + specular + white * clearcoat) * cos_i
+    }
+
+    fn pdf_local(&self, wo: Vec3, wi: Vec3, eta: f64) -> f64 {
+        let [p_diffuse, p_specular, p_clearcoat, p_transmission] = self.lobe_probabilities();
+        let ggx = self.specular_distribution();
+
+        if wi.z < 0.0 {
+            let Some(wm) = transmission_half_vector(wo, wi, eta) else {
+                return 0.0;
+            };
+            let denom = wi.dot(&wm) + wo.dot(&wm) / eta;
+            let dwm_dwi = wi.dot(&wm).abs() / (denom * denom);
+            return p_transmission * ggx.visible_normal_pdf(wo, wm) * dwm_dwi;
+        }
+
+        let h = (wo + wi).unit_vector();
+        let reflection_jacobian = 1.0 / (4.0 * wo.dot(&h));
+        p_diffuse * wi.z / PI
+            + p_specular * ggx.visible_normal_pdf(wo, h) * reflection_jacobian
+            + p_clearcoat
+                * Self::clearcoat_distribution().visible_normal_pdf(wo, h)
+                * reflection_jacobian
+    }
+}
+
+impl Material for Principled {
+    fn eval(&self, wo: &Vec3, wi: &Vec3, record: &HitRecord) -> Color {
+        let onb = Onb::with_tangent(record.normal, record.tangent);
+        let wo = onb.to_local(*wo);
+        if wo.z <= 0.0 {
+            return Color::default();
+        }
+        self.eval_local(wo, onb.to_local(*wi), self.eta(record.front_face))
+    }
+
+    fn sample(&self, ray_in: &Ray, record: &HitRecord) -> Option<BsdfSample> {
+        let onb = Onb::with_tangent(record.normal, record.tangent);
+        let wo = onb.to_local(-ray_in.direction.unit_vector());
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let eta = self.eta(record.front_face);
+
+        // Pick a lobe to sample a direction from, then weight the direction by the whole BSDF
+        // and the combined pdf of all lobes.
+        let [p_diffuse, p_specular, p_clearcoat, _] = self.lobe_probabilities();
+        let u = get_random_f64();
+        let wi = if u < p_diffuse {
+            Vec3::random_cosine_direction()
+        } else if u < p_diffuse + p_specular {
+            let m = self.specular_distribution().sample_visible_normal(
+                wo,
+                get_random_f64(),
+                get_random_f64(),
+            );
+            (-wo).reflect(&m)
+        } else if u < p_diffuse + p_specular + p_clearcoat {
+            let m = Self::clearcoat_distribution().sample_visible_normal(
+                wo,
+                get_random_f64(),
+                get_random_f64(),
+            );
+            (-wo).reflect(&m)
+        } else {
+            let m = self.specular_distribution().sample_visible_normal(
+                wo,
+                get_random_f64(),
+                get_random_f64(),
+            );
+            refract(wo, m, eta)?
+        };
+
+        let pdf = self.pdf_local(wo, wi, eta);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        Some(BsdfSample {
+            ray: ray_in.spawn(record.p, onb.local(wi)),
+            weight: self.eval_local(wo, wi, eta) / pdf,
+            pdf,
+            is_delta: false,
+        })
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, record: &HitRecord) -> f64 {
+        let onb = Onb::with_tangent(record.normal, record.tangent);
+        let wo = onb.to_local(*wo);
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        self.pdf_local(wo, onb.to_local(*wi), self.eta(record.front_face))
+    }
+}
+
+// The microfacet normal that refracts `wo` into `wi`, oriented into the upper hemisphere.
+// Returns `None` if the configuration is impossible for a microfacet facing `wo`.
+fn transmission_half_vector(wo: Vec3, wi: Vec3, eta: f64) -> Option<Vec3> {
+    let mut wm = wi * eta + wo;
+    if wm.is_near_zero() {
+        return None;
+    }
+    wm = wm.unit_vector();
+    if wm.z < 0.0 {
+        wm = -wm;
+    }
+    if wm.dot(&wi) >= 0.0 || wm.dot(&wo) <= 0.0 {
+        return None;
+    }
+    Some(wm)
+}
+
+fn schlick_weight(cos_theta: f64) -> f64 {
+    let m = (1.0 - cos_theta).clamp(0.0, 1.0);
+    let m2 = m * m;
+    m2 * m2 * m
+}
+
+// Lets light through a surface, scattering it diffusely to the other side. Marks where light
+// enters and leaves a subsurface scattering medium.
+pub struct DiffuseTransmission {
+    pub transmittance: Color,
+}
+
+impl Material for DiffuseTransmission {
+    fn eval(&self, wo: &Vec3, wi: &Vec3, record: &HitRecord) -> Color {
+        if wo.dot(&record.normal) * wi.dot(&record.normal) >= 0.0 {
+            return Color::default();
+        }
+        self.transmittance * (wi.dot(&record.normal).abs() / PI)
+    }
+
+    fn sample(&self, ray_in: &Ray, record: &HitRecord) -> Option<BsdfSample> {
+        // The normal faces the incoming ray, so the transmitted light leaves against it.
+        let wi = Onb::new(-record.normal).local(Vec3::random_cosine_direction());
+        Some(BsdfSample {
+            ray: ray_in.spawn(record.p, wi),
+            weight: self.transmittance,
+            pdf: wi.dot(&record.normal).abs() / PI,
+            is_delta: false,
+        })
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, record: &HitRecord) -> f64 {
+        if wo.dot(&record.normal) * wi.dot(&record.normal) >= 0.0 {
+            return 0.0;
+        }
+        wi.dot(&record.normal).abs() / PI
+    }
+}
+
+// The phase function of a participating medium that scatters light equally in all directions.
+// Unlike a BSDF, it has no cosine term.
+pub struct Isotropic {
+    pub albedo: Color,
+}
+
+impl Material for Isotropic {
+    fn eval(&self, _wo: &Vec3, _wi: &Vec3, _record: &HitRecord) -> Color {
+        self.albedo / (4.0 * PI)
+    }
+
+    fn sample(&self, ray_in: &Ray, record: &HitRecord) -> Option<BsdfSample> {
+        Some(BsdfSample {
+            ray: ray_in.spawn(record.p, Vec3::random_unit_vector()),
+            weight: self.albedo,
+            pdf: 1.0 / (4.0 * PI),
+            is_delta: false,
+        })
+    }
+
+    fn pdf(&self, _wo: &Vec3, _wi: &Vec3, _record: &HitRecord) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+}
+
+// The Henyey-Greenstein phase function. Its asymmetry `g`, in (-1, 1), blends from scattering
+// mostly backwards when negative, through isotropic at zero, to mostly forwards when positive,
+// as clouds and smoke do.
+pub struct HenyeyGreenstein {
+    pub albedo: Color,
+    pub g: f64,
+}
+
+impl HenyeyGreenstein {
+    // Density of scattering between directions `wo` and `wi`, which both point away from the
+    // scattering point, so light that carries straight on has `wo . wi = -1`.
+    fn phase(&self, cos_theta: f64) -> f64 {
+        let denominator = 1.0 + self.g * self.g + 2.0 * self.g * cos_theta;
+        (1.0 - self.g * self.g) / (4.0 * PI * denominator * denominator.max(0.0).sqrt())
+    }
+}
+
+impl Material for HenyeyGreenstein {
+    fn eval(&self, wo: &Vec3, wi: &Vec3, _record: &HitRecord) -> Color {
+        self.albedo * self.phase(wo.dot(wi))
+    }
+
+    fn sample(&self, ray_in: &Ray, record: &HitRecord) -> Option<BsdfSample> {
+        // Cosine of the angle between the incoming and scattered directions, by inverting the
+        // phase function's distribution.
+        let g = self.g;
+        let u = get_random_f64();
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+            ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let phi = 2.0 * PI * get_random_f64();
+
+        let direction = ray_in.direction.unit_vector();
+        let wi = Onb::new(direction).local(Vec3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ));
+        Some(BsdfSample {
+            ray: ray_in.spawn(record.p, wi),
+            weight: self.albedo,
+            pdf: self.phase(-cos_theta),
+            is_delta: false,
+        })
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, _record: &HitRecord) -> f64 {
+        self.phase(wo.dot(wi))
+    }
+}
+
+pub struct DiffuseLight {
+    emit: Spectrum,
+    rgb: Color,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Spectrum) -> DiffuseLight {
+        let rgb = emit.to_rgb();
+        DiffuseLight { emit, rgb }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn sample(&self, _ray_in: &Ray, _record: &HitRecord) -> Option<BsdfSample> {
+        None
+    }
+
+    fn emitted(&self, _ray_in: &Ray, record: &HitRecord) -> Color {
+        if record.front_face {
+            self.rgb
+        } else {
+            Color::default()
+        }
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+
+    fn emitted_at(&self, _ray_in: &Ray, record: &HitRecord, wavelength: f64) -> f64 {
+        if record.front_face {
+            self.emit.sample(wavelength)
+        } else {
+            0.0
+        }
+    }
+}
+
+// Radiance emitted by `mat` towards `ray`. In spectral mode (when `wavelength` is set) it is a
+// grey holding the radiance at that wavelength.
+pub fn emitted_radiance(
+    mat: &dyn Material,
+    ray: &Ray,
+    record: &HitRecord,
+    wavelength: Option<f64>,
+) -> Color {
+    match wavelength {
+        Some(wavelength) => {
+            let value = mat.emitted_at(ray, record, wavelength);
+            Color::new(value, value, value)
+        }
+        None => mat.emitted(ray, record),
+    }
+}
+
+// Schlick's approximation of the Fresnel reflectance.
 fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
     let mut r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
     r0 = r0 * r0;
@@ -80,3 +867,96 @@ fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
     let pow2 = pow1 * pow1;
     r0 + (1.0 - r0) * pow2 * pow2 * pow1
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Integrates `density`, a function of the cosine with an axis, over the sphere by the
+    // midpoint rule.
+    fn integrate_over_sphere(density: impl Fn(f64) -> f64, from: f64) -> f64 {
+        let steps = 200_000;
+        let width = (1.0 - from) / steps as f64;
+        (0..steps)
+            .map(|i| density(from + (i as f64 + 0.5) * width) * 2.0 * PI * width)
+            .sum()
+    }
+
+    fn at_cosine(cosine: f64) -> Vec3 {
+        Vec3::new((1.0 - cosine * cosine).sqrt(), 0.0, cosine)
+    }
+
+    #[test]
+    fn fuzz_density_is_normalised() {
+        let center = Vec3::new(0.0, 0.0, 1.0);
+        for radius in [0.3, 0.6, 1.0, 1.5] {
+            let total = integrate_over_sphere(
+                |cosine| fuzz_density(&center, radius, &at_cosine(cosine)),
+                -1.0,
+            );
+            assert!((total - 1.0).abs() < 0.01, "radius {radius}: {total}");
+        }
+    }
+
+    #[test]
+    fn glass_reflects_more_at_grazing_angles() {
+        let glass = Dielectric {
+            refractive_index: 1.5,
+            dispersion: 0.0,
+        };
+        let record = HitRecord {
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            front_face: true,
+            ..Default::default()
+        };
+        let reflected_fraction = |cosine: f64| {
+            let ray = Ray::new(Vec3::default(), -at_cosine(cosine));
+            let samples = 100_000;
+            let reflected = (0..samples)
+                .filter(|_| glass.sample(&ray, &record).unwrap().ray.direction.z > 0.0)
+                .count();
+            reflected as f64 / samples as f64
+        };
+        // Schlick's approximation gives 4% head on and 78% at a cosine of 0.05.
+        assert!((reflected_fraction(1.0) - 0.04).abs() < 0.005);
+        assert!((reflected_fraction(0.05) - 0.78).abs() < 0.01);
+    }
+
+    #[test]
+    fn henyey_greenstein_is_normalised() {
+        for g in [-0.7, 0.0, 0.3, 0.9] {
+            let phase = HenyeyGreenstein {
+                albedo: Color::new(1.0, 1.0, 1.0),
+                g,
+            };
+            let total = integrate_over_sphere(|cosine| phase.phase(cosine), -1.0);
+            assert!((total - 1.0).abs() < 0.01, "g {g}: {total}");
+        }
+    }
+
+    #[test]
+    fn fuzz_density_matches_sampling() {
+        let center = Vec3::new(0.0, 0.0, 1.0);
+        let radius = 0.5;
+        let cone = 0.95;
+
+        let samples = 200_000;
+        let inside = (0..samples)
+            .filter(|_| {
+                (center + Vec3::random_unit_vector() * radius)
+                    .unit_vector()
+                    .z
+                    > cone
+            })
+            .count();
+        let expected = integrate_over_sphere(
+            |cosine| fuzz_density(&center, radius, &at_cosine(cosine)),
+            cone,
+        );
+        let fraction = inside as f64 / samples as f64;
+        assert!(
+            (fraction - expected).abs() < 0.01,
+            "{fraction} vs {expected}"
+        );
+    }
+}
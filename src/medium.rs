@@ -0,0 +1,511 @@
+use std::io;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::color::Color;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::{Dielectric, Isotropic, Material, RoughDielectric};
+use crate::ray::Ray;
+use crate::utils::get_random_f64;
+use crate::vec::{Point3, Vec3};
+
+// A volume of constant density filling a closed boundary, such as smoke, a fog bank or wax.
+// Rays travelling through it scatter at exponentially distributed distances, off
+// `phase_function` (usually `Isotropic`), and pass through it unchanged otherwise. Shadow rays
+// are blocked the same way, so on average they see the volume's transmittance.
+//
+// NOTE: the boundary must be convex, such as a sphere or a box. Rays are taken to be inside it
+// between the first two points where they cross it, so the volume fills in any dents and
+// holes in other shapes.
+//
+// Hits inside a volume aren't on a surface, so they report a zero normal.
+pub struct ConstantMedium {
+    boundary: Box<dyn Hittable>,
+    neg_inv_density: f64,
+    phase_function: Arc<dyn Material>,
+}
+
+impl ConstantMedium {
+    pub fn new(
+        boundary: Box<dyn Hittable>,
+        density: f64,
+        phase_function: Arc<dyn Material>,
+    ) -> ConstantMedium {
+        ConstantMedium {
+            boundary,
+            neg_inv_density: -1.0 / density,
+            phase_function,
+        }
+    }
+}
+
+impl ConstantMedium {
+    // The part of `ray_t` the ray spends inside the boundary, even if it starts inside. Looking
+    // for the boundary behind the ray finds where the ray's line enters it, which is only the
+    // start of the inside for convex boundaries.
+    fn span(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> Option<(f64, f64)> {
+        let enter = self.boundary.hit(ray, f64::NEG_INFINITY..=f64::INFINITY)?.t;
+        let exit = self.boundary.hit(ray, enter + 0.0001..=f64::INFINITY)?.t;
+
+        let enter = enter.max(*ray_t.start()).max(0.0);
+        let exit = exit.min(*ray_t.end());
+        (enter < exit).then_some((enter, exit))
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> Option<HitRecord> {
+        let (enter, exit) = self.span(ray, ray_t)?;
+        let ray_length = ray.direction.length();
+        let distance_inside = (exit - enter) * ray_length;
+        let hit_distance = self.neg_inv_density * get_random_f64().ln();
+        if hit_distance > distance_inside {
+            return None;
+        }
+
+        let t = enter + hit_distance / ray_length;
+        Some(HitRecord {
+            p: ray.at(t),
+            normal: Vec3::default(),
+            mat: Some(self.phase_function.clone()),
+            t,
+            front_face: true,
+            ..HitRecord::default()
+        })
+    }
+
+    // Volumes don't block rays outright, only attenuate them.
+    fn occluded(&self, _ray: &Ray, _ray_t: RangeInclusive<f64>) -> bool {
+        false
+    }
+
+    fn transmittance(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> f64 {
+        self.span(ray, ray_t).map_or(1.0, |(enter, exit)| {
+            ((exit - enter) * ray.direction.length() / self.neg_inv_density).exp()
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+
+    fn visit_materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        visit(&self.phase_function);
+    }
+}
+
+// A medium of constant density filling all of space between the scene's objects, such as fog
+// or haze, which `Scene` holds rather than the world. Unlike a `ConstantMedium` it has no
+// boundary, so every ray travels through it, from the camera and the lights alike.
+//
+// NOTE: nothing reaches through infinitely much of it, so the sky and directional lights are
+// hidden. Fogged scenes should be lit by lights within it.
+pub struct HomogeneousMedium {
+    density: f64,
+    phase_function: Arc<dyn Material>,
+}
+
+impl HomogeneousMedium {
+    pub fn new(density: f64, phase_function: Arc<dyn Material>) -> HomogeneousMedium {
+        HomogeneousMedium {
+            density,
+            phase_function,
+        }
+    }
+
+    // Where along `ray`, within `ray_t`, it scatters off the medium if it gets that far.
+    pub fn hit(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> Option<HitRecord> {
+        if self.density <= 0.0 {
+            return None;
+        }
+
+        let t = ray_t.start().max(0.0)
+            - (1.0 - get_random_f64()).ln() / (self.density * ray.direction.length());
+        (t <= *ray_t.end()).then(|| HitRecord {
+            p: ray.at(t),
+            normal: Vec3::default(),
+            mat: Some(self.phase_function.clone()),
+            t,
+            front_face: true,
+            ..HitRecord::default()
+        })
+    }
+
+    // Fraction of the light travelling along `ray` within `ray_t` that isn't scattered away.
+    pub fn transmittance(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> f64 {
+        if self.density <= 0.0 {
+            return 1.0;
+        }
+        let length = (ray_t.end() - ray_t.start().max(0.0)) * ray.direction.length();
+        (-self.density * length).exp()
+    }
+}
+
+// A volume whose density varies over an axis-aligned box, such as a simulated cloud or smoke
+// plume. Densities are given on a grid of points spanning the box, and interpolated linearly in
+// between. Rays are tracked through it against the densest point (delta tracking), and shadow
+// rays estimate its transmittance by ratio tracking.
+//
+// Hits inside a volume aren't on a surface, so they report a zero normal.
+pub struct GridMedium {
+    min: Point3,
+    max: Point3,
+    // Number of points along x, y and z.
+    resolution: [usize; 3],
+    // Laid out with x varying fastest, then y, then z.
+    densities: Vec<f64>,
+    max_density: f64,
+    phase_function: Arc<dyn Material>,
+}
+
+impl GridMedium {
+    pub fn new(
+        min: Point3,
+        max: Point3,
+        resolution: [usize; 3],
+        densities: Vec<f64>,
+        phase_function: Arc<dyn Material>,
+    ) -> GridMedium {
+        assert_eq!(
+            densities.len(),
+            resolution.iter().product::<usize>(),
+            "Density grid doesn't match its resolution"
+        );
+        let max_density = densities.iter().copied().fold(0.0, f64::max);
+        GridMedium {
+            min,
+            max,
+            resolution,
+            densities,
+            max_density,
+            phase_function,
+        }
+    }
+
+    // Loads a density grid in Mitsuba's binary .vol format, scaling its values by
+    // `density_scale`. Only float32 grids are supported, and only the first channel is used.
+    // Files that can't be read, or aren't such grids, are reported as errors.
+    pub fn load_vol(
+        filename: &str,
+        density_scale: f64,
+        phase_function: Arc<dyn Material>,
+    ) -> io::Result<GridMedium> {
+        let invalid = |message: &str| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{filename}: {message}"))
+        };
+
+        let bytes = std::fs::read(filename)?;
+        if !bytes.starts_with(b"VOL\x03") {
+            return Err(invalid("not a version 3 .vol file"));
+        }
+        if bytes.len() < 48 {
+            return Err(invalid("header is truncated"));
+        }
+
+        let int = |offset: usize| i32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let float = |offset: usize| {
+            f64::from(f32::from_le_bytes(
+                bytes[offset..offset + 4].try_into().unwrap(),
+            ))
+        };
+        if int(4) != 1 {
+            return Err(invalid("only float32 volumes are supported"));
+        }
+
+        let size = |offset: usize| usize::try_from(int(offset)).ok().filter(|&size| size > 0);
+        let (Some(x), Some(y), Some(z), Some(channels)) = (size(8), size(12), size(16), size(20))
+        else {
+            return Err(invalid("resolution and channel count must be positive"));
+        };
+        let resolution = [x, y, z];
+        // Sizes are checked for overflow, as they come straight from the file.
+        let count = x.checked_mul(y).and_then(|xy| xy.checked_mul(z));
+        let length = count
+            .and_then(|count| count.checked_mul(channels))
+            .and_then(|values| values.checked_mul(4))
+            .and_then(|data| data.checked_add(48));
+        let (Some(count), Some(length)) = (count, length) else {
+            return Err(invalid("volume is too large"));
+        };
+        if bytes.len() < length {
+            return Err(invalid("volume data is truncated"));
+        }
+
+        Ok(GridMedium::new(
+            Point3::new(float(24), float(28), float(32)),
+            Point3::new(float(36), float(40), float(44)),
+            resolution,
+            (0..count)
+                .map(|index| float(48 + 4 * index * channels) * density_scale)
+                .collect(),
+            phase_function,
+        ))
+    }
+
+    fn density(&self, p: &Point3) -> f64 {
+        // Grid cell containing `p`, and where `p` lies within it.
+        let mut cell = [0; 3];
+        let mut fraction = [0.0; 3];
+        for axis in 0..3 {
+            let last = (self.resolution[axis] - 1) as f64;
+            let x = ((p[axis] - self.min[axis]) / (self.max[axis] - self.min[axis]) * last)
+                .clamp(0.0, last);
+            cell[axis] = x.floor() as usize;
+            fraction[axis] = x - x.floor();
+        }
+
+        let mut density = 0.0;
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut index = [0; 3];
+            for axis in 0..3 {
+                let offset = (corner >> axis) & 1;
+                weight *= if offset == 1 {
+                    fraction[axis]
+                } else {
+                    1.0 - fraction[axis]
+                };
+                index[axis] = (cell[axis] + offset).min(self.resolution[axis] - 1);
+            }
+            let [x, y, z] = index;
+            density +=
+                weight * self.densities[(z * self.resolution[1] + y) * self.resolution[0] + x];
+        }
+        density
+    }
+
+    // The part of `ray_t` the ray spends inside the box.
+    fn span(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> Option<(f64, f64)> {
+        let (mut enter, mut exit) = (ray_t.start().max(0.0), *ray_t.end());
+        for axis in 0..3 {
+            let inv_direction = 1.0 / ray.direction[axis];
+            let mut t0 = (self.min[axis] - ray.origin[axis]) * inv_direction;
+            let mut t1 = (self.max[axis] - ray.origin[axis]) * inv_direction;
+            if inv_direction < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            enter = enter.max(t0);
+            exit = exit.min(t1);
+            if exit <= enter {
+                return None;
+            }
+        }
+        Some((enter, exit))
+    }
+
+    // Distance along the ray, in units of `t`, to the next collision with the densest point's
+    // density, real or not.
+    fn step(&self, ray: &Ray) -> f64 {
+        -(1.0 - get_random_f64()).ln() / (self.max_density * ray.direction.length())
+    }
+}
+
+impl Hittable for GridMedium {
+    fn hit(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> Option<HitRecord> {
+        if self.max_density <= 0.0 {
+            return None;
+        }
+        let (enter, exit) = self.span(ray, ray_t)?;
+
+        // Collisions are real in proportion to the density where they happen, and null
+        // otherwise, which the ray passes straight through.
+        let mut t = enter;
+        loop {
+            t += self.step(ray);
+            if t >= exit {
+                return None;
+            }
+            if get_random_f64() * self.max_density < self.density(&ray.at(t)) {
+                return Some(HitRecord {
+                    p: ray.at(t),
+                    normal: Vec3::default(),
+                    mat: Some(self.phase_function.clone()),
+                    t,
+                    front_face: true,
+                    ..HitRecord::default()
+                });
+            }
+        }
+    }
+
+    fn occluded(&self, _ray: &Ray, _ray_t: RangeInclusive<f64>) -> bool {
+        false
+    }
+
+    fn transmittance(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> f64 {
+        if self.max_density <= 0.0 {
+            return 1.0;
+        }
+        let Some((enter, exit)) = self.span(ray, ray_t) else {
+            return 1.0;
+        };
+
+        // Each collision lets through the fraction of it that's null.
+        let mut transmittance = 1.0;
+        let mut t = enter;
+        loop {
+            t += self.step(ray);
+            if t >= exit {
+                return transmittance;
+            }
+            transmittance *= 1.0 - self.density(&ray.at(t)) / self.max_density;
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.min, self.max))
+    }
+
+    fn visit_materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        visit(&self.phase_function);
+    }
+}
+
+// A translucent object such as skin, marble, milk or jade, filled with a dense medium that
+// light wanders through before leaving again (random-walk subsurface scattering). The
+// `boundary` is a dielectric interface, rough by default, which reflects part of the light
+// and refracts the rest in or out. Inside, light scatters at distances averaging
+// `mean_free_path`, keeping `albedo` of its energy at each event, until it reaches the boundary
+// again. The boundary must be closed and report which side it's hit from, as spheres and
+// consistently oriented meshes do.
+//
+// Shadow rays can't see through the object, since the interface bends them, so light reaches
+// its inside only through the boundary, where paths sample lights as they leave.
+pub struct Subsurface {
+    boundary: Box<dyn Hittable>,
+    density: f64,
+    phase_function: Arc<dyn Material>,
+    interface: Arc<dyn Material>,
+}
+
+impl Subsurface {
+    pub fn new(boundary: Box<dyn Hittable>, mean_free_path: f64, albedo: Color) -> Subsurface {
+        Subsurface {
+            boundary,
+            density: 1.0 / mean_free_path,
+            phase_function: Arc::new(Isotropic { albedo }),
+            interface: Arc::new(RoughDielectric {
+                refractive_index: 1.4,
+                roughness: 0.3,
+            }),
+        }
+    }
+
+    // Sets the refractive index of the boundary, and how rough it is. A `roughness` of 0 makes
+    // it smooth, like polished jade.
+    pub fn with_interface(mut self, refractive_index: f64, roughness: f64) -> Subsurface {
+        self.interface = if roughness > 0.0 {
+            Arc::new(RoughDielectric {
+                refractive_index,
+                roughness,
+            })
+        } else {
+            Arc::new(Dielectric {
+                refractive_index,
+                dispersion: 0.0,
+            })
+        };
+        self
+    }
+}
+
+impl Hittable for Subsurface {
+    fn hit(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> Option<HitRecord> {
+        // Look past the end of `ray_t` for the boundary, to tell if the ray starts inside.
+        let surface = self.boundary.hit(ray, *ray_t.start()..=f64::INFINITY)?;
+
+        if !surface.front_face {
+            let t = ray_t.start().max(0.0)
+                - (1.0 - get_random_f64()).ln() / (self.density * ray.direction.length());
+            if t < surface.t && t <= *ray_t.end() {
+                return Some(HitRecord {
+                    p: ray.at(t),
+                    normal: Vec3::default(),
+                    mat: Some(self.phase_function.clone()),
+                    t,
+                    front_face: true,
+                    ..HitRecord::default()
+                });
+            }
+        }
+
+        (surface.t <= *ray_t.end()).then(|| HitRecord {
+            mat: Some(self.interface.clone()),
+            ..surface
+        })
+    }
+
+    fn occluded(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> bool {
+        self.boundary.occluded(ray, ray_t)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+    fn visit_materials(&self, visit: &mut dyn FnMut(&Arc<dyn Material>)) {
+        visit(&self.interface);
+        visit(&self.phase_function);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn phase_function() -> Arc<dyn Material> {
+        Arc::new(Isotropic {
+            albedo: Color::new(1.0, 1.0, 1.0),
+        })
+    }
+
+    // A .vol file holding a grid of `densities` with the given resolution over the unit cube.
+    fn vol_file(name: &str, resolution: [i32; 3], densities: &[f32]) -> String {
+        let mut bytes = b"VOL\x03".to_vec();
+        for int in [1, resolution[0], resolution[1], resolution[2], 1] {
+            bytes.extend(i32::to_le_bytes(int));
+        }
+        for float in [0.0f32, 0.0, 0.0, 1.0, 1.0, 1.0].iter().chain(densities) {
+            bytes.extend(float.to_le_bytes());
+        }
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, bytes).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn loads_vol_grids() {
+        let filename = vol_file("raytracer_grid.vol", [2, 1, 1], &[1.0, 3.0]);
+        let medium = GridMedium::load_vol(&filename, 2.0, phase_function()).unwrap();
+        assert_eq!(medium.resolution, [2, 1, 1]);
+        assert_eq!(medium.densities, vec![2.0, 6.0]);
+        assert_eq!(medium.density(&Point3::new(0.5, 0.5, 0.5)), 4.0);
+    }
+
+    #[test]
+    fn reports_invalid_vol_files() {
+        let missing = GridMedium::load_vol("no_such_file.vol", 1.0, phase_function());
+        assert_eq!(missing.err().unwrap().kind(), io::ErrorKind::NotFound);
+
+        let truncated = vol_file("raytracer_truncated.vol", [2, 2, 2], &[1.0]);
+        let error = GridMedium::load_vol(&truncated, 1.0, phase_function())
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("truncated"));
+
+        let oversized = vol_file("raytracer_oversized.vol", [i32::MAX; 3], &[]);
+        let error = GridMedium::load_vol(&oversized, 1.0, phase_function())
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("too large"));
+
+        // A size that doesn't overflow, but is far more data than the file holds.
+        let huge = vol_file("raytracer_huge.vol", [1 << 20, 1 << 20, 1], &[1.0]);
+        let error = GridMedium::load_vol(&huge, 1.0, phase_function())
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("truncated"));
+    }
+}
@@ -0,0 +1,202 @@
+use crate::color::Color;
+use crate::utils::PI;
+use crate::vec::Vec3;
+
+// The GGX (Trowbridge-Reitz) microfacet distribution with the Smith masking-shadowing model.
+// All directions are in the local shading frame, where the macrosurface normal is +z.
+pub struct Ggx {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl Ggx {
+    pub fn new(roughness: f64) -> Ggx {
+        let alpha = roughness_to_alpha(roughness);
+        Ggx {
+            alpha_x: alpha,
+            alpha_y: alpha,
+        }
+    }
+
+    // An anisotropic distribution, stretched along the local x axis as `anisotropy` goes from 0
+    // to 1.
+    pub fn anisotropic(roughness: f64, anisotropy: f64) -> Ggx {
+        let alpha = roughness * roughness;
+        let aspect = (1.0 - 0.9 * anisotropy).sqrt();
+        Ggx {
+            alpha_x: (alpha / aspect).max(1e-3),
+            alpha_y: (alpha * aspect).max(1e-3),
+        }
+    }
+
+    // Distribution of microfacet normals `m`.
+    pub fn d(&self, m: Vec3) -> f64 {
+        if m.z <= 0.0 {
+            return 0.0;
+        }
+        let e = m.x * m.x / (self.alpha_x * self.alpha_x)
+            + m.y * m.y / (self.alpha_y * self.alpha_y)
+            + m.z * m.z;
+        1.0 / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    fn lambda(&self, w: Vec3) -> f64 {
+        if w.z == 0.0 {
+            return f64::INFINITY;
+        }
+        let a2_tan2 = (self.alpha_x * self.alpha_x * w.x * w.x
+            + self.alpha_y * self.alpha_y * w.y * w.y)
+            / (w.z * w.z);
+        (-1.0 + (1.0 + a2_tan2).sqrt()) / 2.0
+    }
+
+    // Smith masking term for a single direction.
+    pub fn g1(&self, w: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // Height-correlated Smith masking-shadowing term.
+    pub fn g2(&self, wo: Vec3, wi: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Samples a microfacet normal from the distribution of normals visible from `wo`
+    // (Heitz 2018). `wo` must be in the upper hemisphere.
+    pub fn sample_visible_normal(&self, wo: Vec3, u1: f64, u2: f64) -> Vec3 {
+        // Stretch the view direction so the distribution becomes the hemisphere.
+        let vh = Vec3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).unit_vector();
+
+        let len_sq = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if len_sq > 0.0 {
+            Vec3::new(-vh.y, vh.x, 0.0) / len_sq.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(&t1);
+
+        // Sample a point on the projected disk, warped towards the visible half.
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+        // Unstretch back to the ellipsoid.
+        Vec3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)).unit_vector()
+    }
+
+    // Density of `sample_visible_normal` returning `m`.
+    pub fn visible_normal_pdf(&self, wo: Vec3, m: Vec3) -> f64 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * wo.dot(&m).max(0.0) * self.d(m) / wo.z
+    }
+}
+
+// Maps the perceptual roughness in [0, 1] to the distribution's alpha, keeping it away from
+// zero where the distribution degenerates.
+pub fn roughness_to_alpha(roughness: f64) -> f64 {
+    (roughness * roughness).max(1e-3)
+}
+
+// Exact Fresnel reflectance of a conductor with complex refractive index eta + ik, evaluated
+// per colour channel.
+pub fn fresnel_conductor(cos_theta_i: f64, eta: Color, k: Color) -> Color {
+    let channel = |eta: f64, k: f64| {
+        let cos2 = cos_theta_i * cos_theta_i;
+        let sin2 = 1.0 - cos2;
+        let eta2 = eta * eta;
+        let k2 = k * k;
+
+        let t0 = eta2 - k2 - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+
+        let t1 = a2_plus_b2 + cos2;
+        let t2 = 2.0 * cos_theta_i * a;
+        let rs = (t1 - t2) / (t1 + t2);
+
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+
+        0.5 * (rp + rs)
+    };
+
+    Color::new(
+        channel(eta.x, k.x),
+        channel(eta.y, k.y),
+        channel(eta.z, k.z),
+    )
+}
+
+// Exact Fresnel reflectance of a dielectric interface for unpolarised light. `eta` is the ratio
+// of the refractive index on the transmitted side to that on the incident side.
+pub fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i) / (eta * eta);
+
+    // Total internal reflection
+    if sin2_theta_t >= 1.0 {
+        return 1.0;
+    }
+
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    let r_parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular)
+}
+
+// Refracts `wo` (pointing away from the surface) through the microfacet with normal `m`.
+// Returns `None` on total internal reflection.
+pub fn refract(wo: Vec3, m: Vec3, eta: f64) -> Option<Vec3> {
+    let cos_theta_i = wo.dot(&m);
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    Some(-wo / eta + m * (cos_theta_i / eta - cos_theta_t))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Integrates `density` over the upper hemisphere by the midpoint rule in the cosine with +z
+    // and the angle around it.
+    fn integrate_over_hemisphere(density: impl Fn(Vec3) -> f64) -> f64 {
+        let (steps_cosine, steps_phi) = (2000, 200);
+        let (width_cosine, width_phi) = (1.0 / steps_cosine as f64, 2.0 * PI / steps_phi as f64);
+        let mut total = 0.0;
+        for i in 0..steps_cosine {
+            let cosine = (i as f64 + 0.5) * width_cosine;
+            let sine = (1.0 - cosine * cosine).sqrt();
+            for j in 0..steps_phi {
+                let phi = (j as f64 + 0.5) * width_phi;
+                let w = Vec3::new(sine * phi.cos(), sine * phi.sin(), cosine);
+                total += density(w) * width_cosine * width_phi;
+            }
+        }
+        total
+    }
+
+    #[test]
+    fn projected_normals_cover_the_surface_once() {
+        for ggx in [Ggx::new(0.3), Ggx::new(0.8), Ggx::anisotropic(0.5, 0.8)] {
+            let total = integrate_over_hemisphere(|m| ggx.d(m) * m.z);
+            assert!((total - 1.0).abs() < 0.01, "{total}");
+        }
+    }
+
+    #[test]
+    fn visible_normal_pdf_is_normalised() {
+        let wo = Vec3::new(0.6, 0.3, 0.5).unit_vector();
+        for ggx in [Ggx::new(0.5), Ggx::anisotropic(0.6, 0.7)] {
+            let total = integrate_over_hemisphere(|m| ggx.visible_normal_pdf(wo, m));
+            assert!((total - 1.0).abs() < 0.01, "{total}");
+        }
+    }
+}
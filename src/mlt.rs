@@ -0,0 +1,342 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+
+use std::cell::RefCell;
+
+use crate::camera::SAMPLES_PER_PIXEL;
+use crate::color::Color;
+use crate::film::Film;
+use crate::integrator::{Integrator, PathTracer, RenderContext};
+use crate::ray::Ray;
+use crate::spectrum::film_rgb;
+use crate::utils::{get_random_f64, set_random_source, PI};
+
+// Primary sample space Metropolis light transport (Kelemen et al. 2002). A path is fully
+// determined by the random numbers the path tracer consumes to build it, including the point
+// on the film it starts from. Markov chains mutate those numbers, either perturbing them
+// slightly or drawing fresh ones, and accept each mutation with a probability that makes them
+// visit paths in proportion to their brightness. Once a chain finds a hard-to-reach light path,
+// such as a caustic seen through glass, it keeps exploring its neighbourhood instead of leaving
+// it to chance.
+//
+// The image's overall brightness is estimated separately, from `bootstrap_samples`
+// independent paths, which also seed the chains. Chains run in parallel and share the
+// `SAMPLES_PER_PIXEL` mutations per pixel between them. Each chain mutates its path with its
+// own random numbers, even when several chains start from the same bootstrap path.
+//
+// NOTE: volumes are sampled by delta tracking, which draws as many random numbers as it takes
+// collisions, so mutations of paths through media are less local than others.
+pub struct Metropolis {
+    pub bootstrap_samples: u32,
+    // Number of Markov chains, which is kept between one and the number of mutations.
+    pub chains: u32,
+    // Probability of a mutation drawing an independent path, rather than a nearby one.
+    pub large_step_probability: f64,
+    // Standard deviation of the perturbation of each random number in the other mutations.
+    pub sigma: f64,
+}
+
+impl Integrator for Metropolis {
+    fn radiance(&self, ray: &Ray, context: &RenderContext, wavelength: Option<f64>) -> Color {
+        PathTracer.radiance(ray, context, wavelength)
+    }
+
+    fn render(&self, context: &RenderContext) -> Vec<Color> {
+        let (width, height) = context.camera.resolution();
+
+        // Seeding a sampler with the index of a bootstrap path reproduces it exactly.
+        let contributions: Vec<f64> = (0..self.bootstrap_samples)
+            .into_par_iter()
+            .map(|seed| {
+                start_chain(PrimarySampler::new(
+                    stream(BOOTSTRAP_STREAM, u64::from(seed)),
+                    self.sigma,
+                ));
+                let contribution = trace(context).contribution();
+                end_chain();
+                contribution
+            })
+            .collect();
+        let total: f64 = contributions.iter().sum();
+        if total == 0.0 {
+            return vec![Color::default(); (width * height) as usize];
+        }
+
+        // Every chain runs, and makes at least one mutation.
+        let mutations = u64::from(width * height) * SAMPLES_PER_PIXEL as u64;
+        let chains = u64::from(self.chains).clamp(1, mutations);
+        let mutations_per_chain = mutations / chains;
+        let film = Film::new(width, height);
+        (0..chains).into_par_iter().for_each(|chain| {
+            let mut rng = stream(CHAIN_STREAM, chain);
+
+            // Start from a bootstrap path picked in proportion to its contribution.
+            let target = rng.gen::<f64>() * total;
+            let mut sum = 0.0;
+            let seed = contributions
+                .iter()
+                .position(|contribution| {
+                    sum += contribution;
+                    sum > target
+                })
+                .unwrap_or(contributions.len() - 1);
+            start_chain(PrimarySampler::new(
+                stream(BOOTSTRAP_STREAM, seed as u64),
+                self.sigma,
+            ));
+            let mut current = trace(context);
+            with_sampler(|sampler| sampler.rng = stream(MUTATION_STREAM, chain));
+
+            for _ in 0..mutations_per_chain {
+                let large_step = rng.gen::<f64>() < self.large_step_probability;
+                with_sampler(|sampler| sampler.start_iteration(large_step));
+                let proposed = trace(context);
+
+                // Splat both paths, weighted by their chances of being the next state, rather
+                // than only the one the chain moves to.
+                let acceptance = if current.contribution() > 0.0 {
+                    (proposed.contribution() / current.contribution()).min(1.0)
+                } else {
+                    1.0
+                };
+                proposed.splat(&film, acceptance);
+                current.splat(&film, 1.0 - acceptance);
+
+                if rng.gen::<f64>() < acceptance {
+                    current = proposed;
+                } else {
+                    with_sampler(PrimarySampler::reject);
+                }
+            }
+            end_chain();
+        });
+
+        // Each path was splatted divided by its contribution, so the average contribution
+        // restores the image's brightness.
+        let scale = total / f64::from(self.bootstrap_samples) * mutations as f64
+            / (mutations_per_chain * chains) as f64;
+        (0..height)
+            .flat_map(|j| (0..width).map(move |i| (i, j)))
+            .map(|(i, j)| film.get(i, j) * scale)
+            .collect()
+    }
+}
+
+// A path sampled from the primary samples, and the pixel it lands on.
+struct PathSample {
+    pixel: (u32, u32),
+    color: Color,
+}
+
+impl PathSample {
+    // The scalar the chains sample paths in proportion to.
+    fn contribution(&self) -> f64 {
+        self.color.luminance()
+    }
+
+    fn splat(&self, film: &Film, weight: f64) {
+        let contribution = self.contribution();
+        if contribution > 0.0 && weight > 0.0 {
+            let (i, j) = self.pixel;
+            film.splat(i, j, self.color * (weight / contribution), None);
+        }
+    }
+}
+
+// Traces the path described by the current primary samples: the first two pick a pixel, and
+// the rest are consumed by the camera and the path tracer as they go.
+fn trace(context: &RenderContext) -> PathSample {
+    let camera = context.camera;
+    let (width, height) = camera.resolution();
+    let i = ((get_random_f64() * width as f64) as u32).min(width - 1);
+    let j = ((get_random_f64() * height as f64) as u32).min(height - 1);
+
+    let ray = camera.sample_ray(i, j);
+    let radiance = PathTracer.radiance(&ray, context, ray.wavelength);
+    PathSample {
+        pixel: (i, j),
+        color: film_rgb(radiance, ray.wavelength),
+    }
+}
+
+// Random number streams, so that no two sources of random numbers share a sequence: one per
+// bootstrap path, one per chain for its own decisions, and one per chain for its mutations.
+const BOOTSTRAP_STREAM: u64 = 0;
+const CHAIN_STREAM: u64 = 1;
+const MUTATION_STREAM: u64 = 2;
+
+// The random number generator for `index` in `stream`.
+fn stream(stream: u64, index: u64) -> StdRng {
+    let mut seed = [0; 32];
+    seed[..8].copy_from_slice(&stream.to_le_bytes());
+    seed[8..16].copy_from_slice(&index.to_le_bytes());
+    StdRng::from_seed(seed)
+}
+
+// The random numbers behind a path, created as the path asks for them and kept across
+// mutations.
+struct PrimarySampler {
+    rng: StdRng,
+    samples: Vec<f64>,
+    // The samples before the current mutation, restored if it's rejected.
+    backup: Vec<f64>,
+    index: usize,
+    sigma: f64,
+}
+
+impl PrimarySampler {
+    fn new(rng: StdRng, sigma: f64) -> PrimarySampler {
+        PrimarySampler {
+            rng,
+            samples: Vec::new(),
+            backup: Vec::new(),
+            index: 0,
+            sigma,
+        }
+    }
+
+    fn start_iteration(&mut self, large_step: bool) {
+        self.backup.clone_from(&self.samples);
+        self.index = 0;
+        for sample in &mut self.samples {
+            *sample = if large_step {
+                self.rng.gen()
+            } else {
+                // Perturb by a normally distributed offset (Box-Muller), wrapping around.
+                let radius = (-2.0 * (1.0 - self.rng.gen::<f64>()).ln()).sqrt();
+                let offset = self.sigma * radius * (2.0 * PI * self.rng.gen::<f64>()).cos();
+                let moved = *sample + offset;
+                let wrapped = moved - moved.floor();
+                if wrapped < 1.0 {
+                    wrapped
+                } else {
+                    0.0
+                }
+            };
+        }
+    }
+
+    fn next(&mut self) -> f64 {
+        // Samples a path hasn't used before are independent of everything before them.
+        if self.index == self.samples.len() {
+            let sample = self.rng.gen();
+            self.samples.push(sample);
+        }
+        self.index += 1;
+        self.samples[self.index - 1]
+    }
+
+    fn reject(&mut self) {
+        std::mem::swap(&mut self.samples, &mut self.backup);
+    }
+}
+
+thread_local! {
+    // The sampler of the chain running on this thread.
+    static SAMPLER: RefCell<Option<PrimarySampler>> = const { RefCell::new(None) };
+}
+
+// Makes `sampler` the source of this thread's random numbers. A chain runs on one thread from
+// start to end, as nothing it calls hands work to other threads.
+fn start_chain(sampler: PrimarySampler) {
+    SAMPLER.with(|current| current.replace(Some(sampler)));
+    set_random_source(Some(next_primary_sample));
+}
+
+fn end_chain() {
+    set_random_source(None);
+    SAMPLER.with(|current| current.replace(None));
+}
+
+fn with_sampler<R>(f: impl FnOnce(&mut PrimarySampler) -> R) -> R {
+    SAMPLER.with(|current| f(current.borrow_mut().as_mut().unwrap()))
+}
+
+fn next_primary_sample() -> f64 {
+    with_sampler(PrimarySampler::next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::hittable::{HittableList, Sphere};
+    use crate::material::{DiffuseLight, Lambertian};
+    use crate::scene::Scene;
+    use crate::spectrum::Spectrum;
+    use crate::vec::Point3;
+    use std::cell::Cell;
+    use std::sync::Arc;
+
+    // Mean of the pixels rendered by `integrator`, in a diffuse room lit by a glowing ball.
+    fn mean_pixel(integrator: &dyn Integrator) -> Color {
+        let mut world = HittableList::new();
+        let white = Arc::new(Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5),
+        });
+        let light = Arc::new(DiffuseLight::new(Spectrum::Constant(4.0)));
+        world.push(Box::new(Sphere::new(Point3::default(), 3.0, white)));
+        world.push(Box::new(Sphere::new(
+            Point3::new(0.5, 1.0, -1.5),
+            0.5,
+            light,
+        )));
+        let pixels = Camera::new(1.0, 16).render_pixels(&Scene::new(world), integrator);
+        pixels
+            .iter()
+            .fold(Color::default(), |sum, &pixel| sum + pixel)
+            / pixels.len() as f64
+    }
+
+    #[test]
+    fn matches_the_path_tracer_brightness() {
+        let expected = mean_pixel(&PathTracer).luminance();
+        for chains in [0, 64, u32::MAX] {
+            let metropolis = Metropolis {
+                bootstrap_samples: 20_000,
+                chains,
+                large_step_probability: 0.3,
+                sigma: 0.01,
+            };
+            let mean = mean_pixel(&metropolis).luminance();
+            assert!(
+                (mean / expected - 1.0).abs() < 0.05,
+                "{chains} chains: {mean} vs {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn chains_from_the_same_path_mutate_it_differently() {
+        let mutated = |chain: u64| {
+            let mut sampler = PrimarySampler::new(stream(BOOTSTRAP_STREAM, 7), 0.01);
+            let path: Vec<f64> = (0..4).map(|_| sampler.next()).collect();
+            sampler.rng = stream(MUTATION_STREAM, chain);
+            sampler.start_iteration(false);
+            (path, sampler.samples)
+        };
+        let (path0, mutated0) = mutated(0);
+        let (path1, mutated1) = mutated(1);
+        assert_eq!(path0, path1);
+        assert_ne!(mutated0, mutated1);
+    }
+
+    #[test]
+    fn directions_take_a_fixed_number_of_samples() {
+        thread_local! {
+            static COUNT: Cell<u32> = const { Cell::new(0) };
+        }
+        fn counted() -> f64 {
+            COUNT.with(|count| count.set(count.get() + 1));
+            // Values that a rejection sampler would have turned down.
+            0.999
+        }
+
+        set_random_source(Some(counted));
+        let direction = crate::vec::Vec3::random_unit_vector();
+        set_random_source(None);
+        assert_eq!(COUNT.with(Cell::get), 2);
+        assert!((direction.length() - 1.0).abs() < 1e-12);
+    }
+}
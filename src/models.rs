@@ -1,23 +1,35 @@
+use cgmath::{Matrix, Matrix3, Matrix4, Quaternion, SquareMatrix, Vector2, Vector3, Zero};
+use gltf::animation::util::ReadOutputs;
+use gltf::animation::Interpolation;
+use gltf::image::Format;
+use image::{DynamicImage, GrayImage, ImageBuffer, Luma};
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
 use std::sync::Arc;
 
+use crate::animation::{Keyframes, Lerp};
 use crate::color::Color;
-use crate::hittable::{Translation, Triangle};
-use crate::material::Lambertian;
+use crate::hittable::{HitRecord, Triangle};
+use crate::material::{BsdfSample, Material, Principled};
+use crate::ray::Ray;
 use crate::vec::{Point3, Vec3};
 
-pub fn load_triangles(filename: &str) -> Vec<Box<Triangle>> {
+// The triangles of every model in the first scene of a glTF file, where the file places them.
+// Use `Camera::with_framing` to bring them into view.
+pub fn load_triangles(filename: &str) -> Vec<Triangle> {
     let scenes = easy_gltf::load(filename).expect("Failed to load glTF");
 
-    let wizard = &scenes[0].models[0];
-
-    let triangles = wizard.triangles().expect("Failed to get triangles");
-    let material = wizard.material();
-
-    let triangles: Vec<_> = wizard
-        .triangles()
-        .expect("Failed to get triangles")
-        .iter()
-        .map(|triangle| {
+    let mut materials = Vec::new();
+    let mut triangles = Vec::new();
+    for model in &scenes[0].models {
+        // Lines and points have no surface to render.
+        let Ok(model_triangles) = model.triangles() else {
+            continue;
+        };
+        let mat = shared_material(&mut materials, model.material());
+        triangles.extend(model_triangles.iter().map(|triangle| {
             let p1 = Point3::new(
                 triangle[0].position[0] as f64,
                 triangle[0].position[1] as f64,
@@ -39,17 +51,776 @@ pub fn load_triangles(filename: &str) -> Vec<Box<Triangle>> {
                 triangle[0].normal.z as f64,
             );
 
-            let color = material.get_base_color(triangle[0].tex_coords);
-            let albedo = Color::get_color(color.x, color.y, color.z);
+            Triangle::new_with_normal(p1, p2, p3, normal, mat.clone())
+                .with_tex_coords(triangle.map(|vertex| tex_coords(vertex.tex_coords)))
+        }));
+    }
+
+    triangles
+}
+
+// The triangles of a Wavefront OBJ file, with their materials from the MTL files it names as
+// principled BSDFs. Polygons are split into fans of triangles. Materials use the colors and
+// PBR parameters of their MTL entries, but not their textures, and faces before any `usemtl`
+// get the default principled material. Files that can't be read, or that refer to vertices
+// or materials they don't define, are reported as errors.
+pub fn load_obj(filename: &str) -> io::Result<Vec<Triangle>> {
+    let invalid = |line: usize, message: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{filename}:{line}: {message}"),
+        )
+    };
+    let directory = Path::new(filename).parent().unwrap_or(Path::new(""));
+
+    let mut positions: Vec<Point3> = Vec::new();
+    let mut tex_coords: Vec<[f64; 2]> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+    let mut material: Arc<dyn Material> = Arc::new(Principled::default());
+    let mut triangles = Vec::new();
+
+    for (index, line) in std::fs::read_to_string(filename)?.lines().enumerate() {
+        let line_number = index + 1;
+        let mut words = line.split_whitespace();
+        let Some(keyword) = words.next() else {
+            continue;
+        };
+        let numbers = |count: usize| -> io::Result<Vec<f64>> {
+            let numbers: Vec<f64> = line
+                .split_whitespace()
+                .skip(1)
+                .take(count)
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .map_err(|_| invalid(line_number, "expected numbers"))?;
+            if numbers.len() < count {
+                return Err(invalid(line_number, "too few numbers"));
+            }
+            Ok(numbers)
+        };
+
+        match keyword {
+            "v" => {
+                let v = numbers(3)?;
+                positions.push(Point3::new(v[0], v[1], v[2]));
+            }
+            "vt" => {
+                let vt = numbers(2)?;
+                tex_coords.push([vt[0], vt[1]]);
+            }
+            "vn" => {
+                let vn = numbers(3)?;
+                normals.push(Vec3::new(vn[0], vn[1], vn[2]));
+            }
+            "mtllib" => {
+                for library in words {
+                    materials.extend(load_mtl(&directory.join(library))?);
+                }
+            }
+            "usemtl" => {
+                let name = words.next().unwrap_or_default();
+                material = materials
+                    .get(name)
+                    .ok_or_else(|| invalid(line_number, &format!("no material `{name}`")))?
+                    .clone();
+            }
+            "f" => {
+                // Each corner is `v`, `v/vt`, `v//vn` or `v/vt/vn`, counting from 1, or back
+                // from the latest vertex when negative.
+                let index = |part: Option<&str>, count: usize| -> io::Result<Option<usize>> {
+                    match part.filter(|part| !part.is_empty()) {
+                        None => Ok(None),
+                        Some(part) => part
+                            .parse()
+                            .ok()
+                            .and_then(|index| obj_index(index, count))
+                            .map(Some)
+                            .ok_or_else(|| invalid(line_number, "invalid vertex index")),
+                    }
+                };
+                let corners = words
+                    .map(|corner| {
+                        let mut parts = corner.split('/');
+                        let position = index(parts.next(), positions.len())?
+                            .ok_or_else(|| invalid(line_number, "corner without a vertex"))?;
+                        let tex_coord = index(parts.next(), tex_coords.len())?;
+                        let normal = index(parts.next(), normals.len())?;
+                        Ok((position, tex_coord, normal))
+                    })
+                    .collect::<io::Result<Vec<_>>>()?;
+                if corners.len() < 3 {
+                    return Err(invalid(line_number, "faces need at least three corners"));
+                }
+
+                for i in 1..corners.len() - 1 {
+                    let [a, b, c] = [corners[0], corners[i], corners[i + 1]];
+                    let (p1, p2, p3) = (positions[a.0], positions[b.0], positions[c.0]);
+                    let triangle = match a.2 {
+                        Some(normal) => {
+                            Triangle::new_with_normal(p1, p2, p3, normals[normal], material.clone())
+                        }
+                        None => Triangle::new(p1, p2, p3, material.clone()),
+                    };
+                    triangles.push(match (a.1, b.1, c.1) {
+                        (Some(ta), Some(tb), Some(tc)) => triangle.with_tex_coords([
+                            tex_coords[ta],
+                            tex_coords[tb],
+                            tex_coords[tc],
+                        ]),
+                        _ => triangle,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(triangles)
+}
+
+// The materials of an MTL file by name. Colors and PBR parameters are read, with Phong
+// exponents, dissolve and refractive indices converted to roughness, transmission and
+// specular.
+fn load_mtl(path: &Path) -> io::Result<HashMap<String, Arc<dyn Material>>> {
+    let filename = path.display();
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, Principled)> = None;
+
+    for (index, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+        let mut words = line.split_whitespace();
+        let Some(keyword) = words.next() else {
+            continue;
+        };
+        let values: Vec<f64> = words.clone().filter_map(|word| word.parse().ok()).collect();
+        let value = || {
+            values.first().copied().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{filename}:{}: expected a number", index + 1),
+                )
+            })
+        };
+
+        if keyword == "newmtl" {
+            if let Some((name, principled)) = current.take() {
+                materials.insert(name, Arc::new(principled) as Arc<dyn Material>);
+            }
+            let name = words.next().unwrap_or_default().to_string();
+            current = Some((name, Principled::default()));
+            continue;
+        }
+        let Some((_, principled)) = current.as_mut() else {
+            continue;
+        };
+        match keyword {
+            "Kd" if values.len() >= 3 => {
+                principled.base_color = Color::new(values[0], values[1], values[2]);
+            }
+            // Blinn-Phong exponents map to GGX roughness by alpha = sqrt(2 / (Ns + 2)).
+            "Ns" => principled.roughness = (2.0 / (value()?.max(0.0) + 2.0)).powf(0.25),
+            "Ni" => {
+                let ior = value()?;
+                let f0 = ((ior - 1.0) / (ior + 1.0)).powi(2);
+                principled.specular = (f0 / 0.08).clamp(0.0, 1.0);
+            }
+            "d" => principled.transmission = 1.0 - value()?.clamp(0.0, 1.0),
+            "Tr" => principled.transmission = value()?.clamp(0.0, 1.0),
+            "Pr" => principled.roughness = value()?.clamp(0.0, 1.0),
+            "Pm" => principled.metallic = value()?.clamp(0.0, 1.0),
+            "Ps" => principled.sheen = value()?.clamp(0.0, 1.0),
+            "Pc" => principled.clearcoat = value()?.clamp(0.0, 1.0),
+            "aniso" => principled.anisotropic = value()?.clamp(0.0, 1.0),
+            _ => {}
+        }
+    }
+    if let Some((name, principled)) = current {
+        materials.insert(name, Arc::new(principled) as Arc<dyn Material>);
+    }
+
+    Ok(materials)
+}
+
+// The position in a list of `count` elements of the OBJ index `index`.
+fn obj_index(index: i64, count: usize) -> Option<usize> {
+    let index = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+    usize::try_from(index).ok().filter(|&index| index < count)
+}
+
+// A glTF material as a principled BSDF, with its textures looked up at the texture coordinates
+// of each hit.
+struct GltfMaterial {
+    material: Arc<easy_gltf::Material>,
+}
+
+impl GltfMaterial {
+    fn at(&self, record: &HitRecord) -> Principled {
+        principled(
+            &self.material,
+            Vector2::new(record.u as f32, record.v as f32),
+        )
+    }
+}
+
+impl Material for GltfMaterial {
+    fn eval(&self, wo: &Vec3, wi: &Vec3, record: &HitRecord) -> Color {
+        self.at(record).eval(wo, wi, record)
+    }
+
+    fn sample(&self, ray_in: &Ray, record: &HitRecord) -> Option<BsdfSample> {
+        self.at(record).sample(ray_in, record)
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, record: &HitRecord) -> f64 {
+        self.at(record).pdf(wo, wi, record)
+    }
+}
+
+// The material for the glTF `material`, shared by every model using it. `materials` holds
+// those made so far.
+fn shared_material(
+    materials: &mut Vec<(Arc<easy_gltf::Material>, Arc<dyn Material>)>,
+    material: Arc<easy_gltf::Material>,
+) -> Arc<dyn Material> {
+    if let Some((_, mat)) = materials
+        .iter()
+        .find(|(loaded, _)| Arc::ptr_eq(loaded, &material))
+    {
+        return mat.clone();
+    }
+    let mat: Arc<dyn Material> = Arc::new(GltfMaterial {
+        material: material.clone(),
+    });
+    materials.push((material, mat.clone()));
+    mat
+}
 
-            let mat = Lambertian { albedo };
-            let triangle = Triangle::new_with_normal(p1, p2, p3, normal, Arc::new(mat))
-                .scale(4.0)
-                .translate(Translation::Down(7.0))
-                .translate(Translation::Backward(3.0));
-            Box::new(triangle)
+// The glTF material at `tex_coords`, as a principled BSDF.
+fn principled(material: &easy_gltf::Material, tex_coords: Vector2<f32>) -> Principled {
+    let color = material.get_base_color(tex_coords);
+    Principled {
+        base_color: Color::get_color(color.x, color.y, color.z),
+        metallic: material.get_metallic(tex_coords) as f64,
+        roughness: material.get_roughness(tex_coords) as f64,
+        ..Default::default()
+    }
+}
+
+fn tex_coords(v: Vector2<f32>) -> [f64; 2] {
+    [f64::from(v.x), f64::from(v.y)]
+}
+
+// The glTF `material` in the form easy-gltf loads it, with the metallic-roughness parameters
+// and textures `principled` reads. Textures in formats other than 8 bits per channel are left
+// out.
+fn easy_gltf_material(
+    material: &gltf::Material,
+    images: &[gltf::image::Data],
+) -> easy_gltf::Material {
+    let pbr = material.pbr_metallic_roughness();
+    let texture = |info: Option<gltf::texture::Info>| {
+        let data = &images[info?.texture().source().index()];
+        let pixels = data.pixels.clone();
+        let (width, height) = (data.width, data.height);
+        let image = match data.format {
+            Format::R8 => DynamicImage::ImageLuma8(ImageBuffer::from_raw(width, height, pixels)?),
+            Format::R8G8 => {
+                DynamicImage::ImageLumaA8(ImageBuffer::from_raw(width, height, pixels)?)
+            }
+            Format::R8G8B8 => {
+                DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, pixels)?)
+            }
+            Format::R8G8B8A8 => {
+                DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, pixels)?)
+            }
+            _ => return None,
+        };
+        Some(image.to_rgba8())
+    };
+    // Metalness is in the blue channel, and roughness in the green one.
+    let metallic_roughness = texture(pbr.metallic_roughness_texture());
+    let channel = |channel: usize| {
+        metallic_roughness.as_ref().map(|image| {
+            Arc::new(GrayImage::from_fn(image.width(), image.height(), |x, y| {
+                Luma([image[(x, y)][channel]])
+            }))
         })
-        .collect();
+    };
 
-    triangles
+    easy_gltf::Material {
+        pbr: easy_gltf::model::PbrMaterial {
+            base_color_factor: pbr.base_color_factor().into(),
+            base_color_texture: texture(pbr.base_color_texture()).map(Arc::new),
+            metallic_factor: pbr.metallic_factor(),
+            metallic_texture: channel(2),
+            roughness_factor: pbr.roughness_factor(),
+            roughness_texture: channel(1),
+        },
+        ..Default::default()
+    }
+}
+
+// A glTF model played back through its first animation, which moves its nodes and deforms the
+// meshes bound to skins. Channels interpolated with cubic splines are followed linearly
+// between their keyframes, and morph targets are ignored.
+pub struct AnimatedModel {
+    nodes: Vec<AnimatedNode>,
+    // Nodes at the top of the hierarchy of the first scene.
+    roots: Vec<usize>,
+    meshes: Vec<AnimatedMesh>,
+    duration: f64,
+}
+
+struct AnimatedNode {
+    children: Vec<usize>,
+    // The node's transform when it isn't animated.
+    translation: Vec3,
+    rotation: Quaternion<f64>,
+    scale: Vec3,
+    translation_keys: Option<Keyframes<Vec3>>,
+    rotation_keys: Option<Keyframes<Quaternion<f64>>>,
+    scale_keys: Option<Keyframes<Vec3>>,
+}
+
+// A mesh primitive, with its vertices in the space of the node holding it.
+struct AnimatedMesh {
+    node: usize,
+    positions: Vec<Vector3<f64>>,
+    // Triangles without normals face the way their winding gives.
+    normals: Option<Vec<Vector3<f64>>>,
+    indices: Vec<u32>,
+    skin: Option<Skin>,
+    tex_coords: Vec<[f64; 2]>,
+    material: Arc<dyn Material>,
+}
+
+// Binds each vertex to up to four joints, which deform it as they move.
+struct Skin {
+    joints: Vec<usize>,
+    // Take the mesh from its own space to each joint's.
+    inverse_bind_matrices: Vec<Matrix4<f64>>,
+    vertex_joints: Vec<[u16; 4]>,
+    vertex_weights: Vec<[f32; 4]>,
+}
+
+impl AnimatedModel {
+    pub fn load(filename: &str) -> AnimatedModel {
+        let (document, buffers, images) = gltf::import(filename).expect("Failed to load glTF");
+        let buffer = |buffer: gltf::Buffer| Some(&*buffers[buffer.index()]);
+        // Keyed by the index of the glTF material, which the default material doesn't have.
+        let mut materials: HashMap<Option<usize>, Arc<dyn Material>> = HashMap::new();
+
+        let mut nodes: Vec<AnimatedNode> = document
+            .nodes()
+            .map(|node| {
+                let (translation, rotation, scale) = node.transform().decomposed();
+                AnimatedNode {
+                    children: node.children().map(|child| child.index()).collect(),
+                    translation: vec3(translation),
+                    rotation: quaternion(rotation),
+                    scale: vec3(scale),
+                    translation_keys: None,
+                    rotation_keys: None,
+                    scale_keys: None,
+                }
+            })
+            .collect();
+
+        let mut duration: f64 = 0.0;
+        if let Some(animation) = document.animations().next() {
+            for channel in animation.channels() {
+                let reader = channel.reader(buffer);
+                let (Some(inputs), Some(outputs)) = (reader.read_inputs(), reader.read_outputs())
+                else {
+                    continue;
+                };
+                let times: Vec<f64> = inputs.map(f64::from).collect();
+                duration = times.iter().copied().fold(duration, f64::max);
+
+                let interpolation = channel.sampler().interpolation();
+                let node = &mut nodes[channel.target().node().index()];
+                match outputs {
+                    ReadOutputs::Translations(values) => {
+                        node.translation_keys =
+                            Some(keyframes(&times, values.map(vec3).collect(), interpolation));
+                    }
+                    ReadOutputs::Rotations(values) => {
+                        let values = values.into_f32().map(quaternion).collect();
+                        node.rotation_keys = Some(keyframes(&times, values, interpolation));
+                    }
+                    ReadOutputs::Scales(values) => {
+                        node.scale_keys =
+                            Some(keyframes(&times, values.map(vec3).collect(), interpolation));
+                    }
+                    ReadOutputs::MorphTargetWeights(_) => {}
+                }
+            }
+        }
+
+        // Lines and points have no surface to render, so only triangle lists are kept.
+        let scene = document.scenes().next().expect("No scene in glTF");
+        let mut mesh_nodes = Vec::new();
+        for node in scene.nodes() {
+            visit_meshes(&node, &mut mesh_nodes);
+        }
+        let meshes = mesh_nodes
+            .into_iter()
+            .filter(|(_, primitive)| primitive.mode() == gltf::mesh::Mode::Triangles)
+            .map(|(node, primitive)| {
+                let reader = primitive.reader(buffer);
+                let positions: Vec<_> = reader
+                    .read_positions()
+                    .expect("The model primitive doesn't contain positions")
+                    .map(vector3)
+                    .collect();
+                let normals = reader
+                    .read_normals()
+                    .map(|normals| normals.map(vector3).collect());
+                let indices = reader.read_indices().map_or_else(
+                    || (0..positions.len() as u32).collect(),
+                    |indices| indices.into_u32().collect(),
+                );
+                let tex_coords = reader.read_tex_coords(0).map_or_else(
+                    || vec![[0.0, 0.0]; positions.len()],
+                    |tex_coords| {
+                        tex_coords
+                            .into_f32()
+                            .map(|[u, v]| [f64::from(u), f64::from(v)])
+                            .collect()
+                    },
+                );
+                let material = primitive.material();
+                let material = materials
+                    .entry(material.index())
+                    .or_insert_with(|| {
+                        Arc::new(GltfMaterial {
+                            material: Arc::new(easy_gltf_material(&material, &images)),
+                        })
+                    })
+                    .clone();
+
+                let skin = node.skin().and_then(|skin| {
+                    let vertex_joints = reader.read_joints(0)?.into_u16().collect();
+                    let vertex_weights = reader.read_weights(0)?.into_f32().collect();
+                    let inverse_bind_matrices = skin.reader(buffer).read_inverse_bind_matrices();
+                    Some(Skin {
+                        joints: skin.joints().map(|joint| joint.index()).collect(),
+                        inverse_bind_matrices: match inverse_bind_matrices {
+                            Some(matrices) => matrices.map(matrix4).collect(),
+                            None => vec![Matrix4::identity(); skin.joints().count()],
+                        },
+                        vertex_joints,
+                        vertex_weights,
+                    })
+                });
+
+                AnimatedMesh {
+                    node: node.index(),
+                    positions,
+                    normals,
+                    indices,
+                    skin,
+                    tex_coords,
+                    material,
+                }
+            })
+            .collect();
+
+        AnimatedModel {
+            nodes,
+            roots: scene.nodes().map(|node| node.index()).collect(),
+            meshes,
+            duration,
+        }
+    }
+
+    // Length of the animation in seconds.
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    // The model's triangles as posed `time` seconds into the animation.
+    pub fn triangles_at(&self, time: f64) -> Vec<Triangle> {
+        let mut transforms = vec![Matrix4::identity(); self.nodes.len()];
+        for &root in &self.roots {
+            self.pose(root, &Matrix4::identity(), time, &mut transforms);
+        }
+
+        let mut triangles = Vec::new();
+        for mesh in &self.meshes {
+            // Skinned vertices follow their joints, and ignore the transform of their node.
+            let joint_matrices: Vec<Matrix4<f64>> =
+                mesh.skin.as_ref().map_or_else(Vec::new, |skin| {
+                    skin.joints
+                        .iter()
+                        .zip(&skin.inverse_bind_matrices)
+                        .map(|(&joint, inverse_bind)| transforms[joint] * inverse_bind)
+                        .collect()
+                });
+            let vertex_transform = |vertex: usize| match &mesh.skin {
+                Some(skin) => {
+                    let weights = skin.vertex_weights[vertex];
+                    skin.vertex_joints[vertex]
+                        .iter()
+                        .zip(weights)
+                        .map(|(&joint, weight)| {
+                            joint_matrices[usize::from(joint)] * f64::from(weight)
+                        })
+                        .fold(Matrix4::zero(), |sum, matrix| sum + matrix)
+                }
+                None => transforms[mesh.node],
+            };
+
+            // Normals stay perpendicular to the surface under non-uniform scales by going
+            // through the inverse transpose. Missing or degenerate ones are left out.
+            let vertices: Vec<(Point3, Option<Vec3>)> = (0..mesh.positions.len())
+                .map(|vertex| {
+                    let transform = vertex_transform(vertex);
+                    let p = transform * mesh.positions[vertex].extend(1.0);
+                    let normal = mesh.normals.as_ref().and_then(|normals| {
+                        let linear = Matrix3::from_cols(
+                            transform.x.truncate(),
+                            transform.y.truncate(),
+                            transform.z.truncate(),
+                        );
+                        let normal = linear.invert()?.transpose() * normals[vertex];
+                        let normal = Vec3::new(normal.x, normal.y, normal.z);
+                        (!normal.is_near_zero()).then(|| normal.unit_vector())
+                    });
+                    (Point3::new(p.x, p.y, p.z), normal)
+                })
+                .collect();
+
+            for triangle in mesh.indices.chunks_exact(3) {
+                let [a, b, c] = [0, 1, 2].map(|corner| triangle[corner] as usize);
+                let (p1, p2, p3) = (vertices[a].0, vertices[b].0, vertices[c].0);
+                let material = mesh.material.clone();
+                let triangle = match vertices[a].1 {
+                    Some(normal) => Triangle::new_with_normal(p1, p2, p3, normal, material),
+                    None => Triangle::new(p1, p2, p3, material),
+                };
+                triangles.push(
+                    triangle.with_tex_coords([a, b, c].map(|vertex| mesh.tex_coords[vertex])),
+                );
+            }
+        }
+        triangles
+    }
+
+    // Sets the transforms of `node` and its descendants at `time`, given that of its parent.
+    fn pose(&self, node: usize, parent: &Matrix4<f64>, time: f64, transforms: &mut [Matrix4<f64>]) {
+        let animated = &self.nodes[node];
+        let translation = animated
+            .translation_keys
+            .as_ref()
+            .map_or(animated.translation, |keys| keys.at(time));
+        let rotation = animated
+            .rotation_keys
+            .as_ref()
+            .map_or(animated.rotation, |keys| keys.at(time));
+        let scale = animated
+            .scale_keys
+            .as_ref()
+            .map_or(animated.scale, |keys| keys.at(time));
+
+        transforms[node] = parent
+            * Matrix4::from_translation(Vector3::new(translation.x, translation.y, translation.z))
+            * Matrix4::from(rotation)
+            * Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z);
+        let transform = transforms[node];
+        for &child in &animated.children {
+            self.pose(child, &transform, time, transforms);
+        }
+    }
+}
+
+// The primitives under `node` and its descendants.
+fn visit_meshes<'a>(
+    node: &gltf::Node<'a>,
+    meshes: &mut Vec<(gltf::Node<'a>, gltf::Primitive<'a>)>,
+) {
+    for child in node.children() {
+        visit_meshes(&child, meshes);
+    }
+    if let Some(mesh) = node.mesh() {
+        for primitive in mesh.primitives() {
+            meshes.push((node.clone(), primitive));
+        }
+    }
+}
+
+// Keyframes from an animation sampler. Stepped values are held until the next keyframe, and
+// cubic splines keep only the values between their tangents.
+fn keyframes<T: Lerp + Clone>(
+    times: &[f64],
+    values: Vec<T>,
+    interpolation: Interpolation,
+) -> Keyframes<T> {
+    let values: Vec<T> = match interpolation {
+        Interpolation::CubicSpline => values.into_iter().skip(1).step_by(3).collect(),
+        _ => values,
+    };
+
+    let mut keys = Vec::new();
+    for (index, (&time, value)) in times.iter().zip(values).enumerate() {
+        if interpolation == Interpolation::Step && index > 0 {
+            let (_, previous): &(f64, T) = &keys[keys.len() - 1];
+            keys.push((time, previous.clone()));
+        }
+        keys.push((time, value));
+    }
+    Keyframes::new(keys)
+}
+
+fn vec3(v: [f32; 3]) -> Vec3 {
+    Vec3::new(f64::from(v[0]), f64::from(v[1]), f64::from(v[2]))
+}
+
+fn vector3(v: [f32; 3]) -> Vector3<f64> {
+    Vector3::new(f64::from(v[0]), f64::from(v[1]), f64::from(v[2]))
+}
+
+// glTF stores quaternions as x, y, z, w.
+fn quaternion(q: [f32; 4]) -> Quaternion<f64> {
+    Quaternion::new(
+        f64::from(q[3]),
+        f64::from(q[0]),
+        f64::from(q[1]),
+        f64::from(q[2]),
+    )
+}
+
+// glTF stores matrices column by column, as cgmath does.
+fn matrix4(m: [[f32; 4]; 4]) -> Matrix4<f64> {
+    Matrix4::from(m.map(|column| column.map(f64::from)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Hittable;
+
+    fn write(name: &str, contents: impl AsRef<[u8]>) -> String {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn loads_obj_polygons_with_materials() {
+        write(
+            "raytracer_quad.mtl",
+            "newmtl red\nKd 0.8 0.1 0.1\nPm 1\nPr 0.2\n",
+        );
+        let filename = write(
+            "raytracer_quad.obj",
+            "mtllib raytracer_quad.mtl\n\
+             v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+             vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nvn 0 0 1\n\
+             usemtl red\nf 1/1/1 2/2/1 3/3/1 -1/-1/-1\n",
+        );
+        let triangles = load_obj(&filename).unwrap();
+        assert_eq!(triangles.len(), 2);
+
+        let ray = Ray::new(Point3::new(0.25, 0.75, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let record = triangles
+            .iter()
+            .find_map(|triangle| triangle.hit(&ray, 0.001..=f64::INFINITY))
+            .unwrap();
+        assert!(record.front_face);
+        assert!((record.u - 0.25).abs() < 1e-12 && (record.v - 0.75).abs() < 1e-12);
+        // Metallic red reflects red light only.
+        let wi = Vec3::new(0.0, 0.0, 1.0);
+        let color = record.mat.clone().unwrap().eval(&wi, &wi, &record);
+        assert!(color.x > 5.0 * color.y);
+    }
+
+    #[test]
+    fn pairs_gltf_primitives_with_their_own_materials() {
+        // A blue triangle and a point cloud in the parent node's mesh, and a red triangle in
+        // its child's.
+        let positions: [[f32; 3]; 6] = [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [2.0, 0.0, 0.0],
+            [3.0, 0.0, 0.0],
+            [2.0, 1.0, 0.0],
+        ];
+        write(
+            "raytracer_primitives.bin",
+            positions
+                .as_flattened()
+                .iter()
+                .flat_map(|x| x.to_le_bytes())
+                .collect::<Vec<_>>(),
+        );
+        let accessor = |offset: usize, min: f32| {
+            format!(
+                r#"{{"bufferView": 0, "byteOffset": {offset}, "componentType": 5126, "count": 3,
+                    "type": "VEC3", "min": [{min}, 0, 0], "max": [{}, 1, 0]}}"#,
+                min + 1.0
+            )
+        };
+        let filename = write(
+            "raytracer_primitives.gltf",
+            format!(
+                r#"{{
+                    "asset": {{"version": "2.0"}},
+                    "scene": 0,
+                    "scenes": [{{"nodes": [0]}}],
+                    "nodes": [{{"mesh": 0, "children": [1]}}, {{"mesh": 1}}],
+                    "meshes": [
+                        {{"primitives": [
+                            {{"attributes": {{"POSITION": 1}}, "mode": 0}},
+                            {{"attributes": {{"POSITION": 0}}, "material": 1}}
+                        ]}},
+                        {{"primitives": [{{"attributes": {{"POSITION": 1}}, "material": 0}}]}}
+                    ],
+                    "materials": [
+                        {{"pbrMetallicRoughness": {{"baseColorFactor": [1, 0, 0, 1], "metallicFactor": 0}}}},
+                        {{"pbrMetallicRoughness": {{"baseColorFactor": [0, 0, 1, 1], "metallicFactor": 0}}}}
+                    ],
+                    "buffers": [{{"uri": "raytracer_primitives.bin", "byteLength": 72}}],
+                    "bufferViews": [{{"buffer": 0, "byteLength": 72}}],
+                    "accessors": [{}, {}]
+                }}"#,
+                accessor(0, 0.0),
+                accessor(36, 2.0)
+            ),
+        );
+
+        let triangles = AnimatedModel::load(&filename).triangles_at(0.0);
+        assert_eq!(triangles.len(), 2);
+        let color_at = |x: f64| {
+            let ray = Ray::new(Point3::new(x, 0.25, 1.0), Vec3::new(0.0, 0.0, -1.0));
+            let record = triangles
+                .iter()
+                .find_map(|triangle| triangle.hit(&ray, 0.001..=f64::INFINITY))
+                .unwrap();
+            let wi = Vec3::new(0.0, 0.0, 1.0);
+            record.mat.clone().unwrap().eval(&wi, &wi, &record)
+        };
+        let (blue, red) = (color_at(0.25), color_at(2.25));
+        assert!(blue.z > blue.x);
+        assert!(red.x > red.z);
+    }
+
+    #[test]
+    fn reports_invalid_obj_files() {
+        let missing = load_obj("no_such_file.obj");
+        assert_eq!(missing.err().unwrap().kind(), io::ErrorKind::NotFound);
+
+        let filename = write("raytracer_invalid.obj", "v 0 0 0\nv 1 0 0\nf 1 2 3\n");
+        let error = load_obj(&filename).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().ends_with(":3: invalid vertex index"));
+
+        let filename = write("raytracer_unknown.obj", "usemtl missing\n");
+        assert!(load_obj(&filename).is_err());
+    }
 }
@@ -0,0 +1,49 @@
+use crate::vec::Vec3;
+
+// An orthonormal basis whose `w` axis is aligned with a surface normal. Shading code works in
+// this local frame, where the normal is +z.
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    // NOTE: `normal` is assumed to have unit length.
+    pub fn new(normal: Vec3) -> Onb {
+        let a = if normal.x.abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = normal.cross(&a).unit_vector();
+        let u = normal.cross(&v);
+        Onb { u, v, w: normal }
+    }
+
+    // A basis with `u` along `tangent`, made perpendicular to `normal`, so anisotropic shading
+    // follows the surface. Falls back to `new` where there's no usable tangent.
+    // NOTE: `normal` is assumed to have unit length.
+    pub fn with_tangent(normal: Vec3, tangent: Vec3) -> Onb {
+        let u = tangent - normal * tangent.dot(&normal);
+        if u.is_near_zero() {
+            return Onb::new(normal);
+        }
+        let u = u.unit_vector();
+        Onb {
+            u,
+            v: normal.cross(&u),
+            w: normal,
+        }
+    }
+
+    // Transforms a vector from the local frame to world space.
+    pub fn local(&self, a: Vec3) -> Vec3 {
+        self.u * a.x + self.v * a.y + self.w * a.z
+    }
+
+    // Transforms a world space vector into the local frame.
+    pub fn to_local(&self, a: Vec3) -> Vec3 {
+        Vec3::new(a.dot(&self.u), a.dot(&self.v), a.dot(&self.w))
+    }
+}
@@ -0,0 +1,307 @@
+use rayon::prelude::*;
+
+use crate::color::Color;
+use crate::hittable::HitRecord;
+use crate::integrator::{russian_roulette, trace_path, Integrator, RenderContext, MAX_DEPTH};
+use crate::light::LightList;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::spectrum::{sample_wavelength, upsample, wavelength_to_rgb};
+use crate::utils::{get_random_f64, PI};
+use crate::vec::{Point3, Vec3};
+
+// Path tracing with a caustics photon map (Jensen 1996). Before rendering, photons are shot
+// from the lights through mirrors and glass, and stored where they land on the first surface
+// that isn't perfectly specular. Caustics are then read from the map by density estimation at
+// the first diffuse hit of each camera path, in place of the rare paths that reach a light
+// through a chain of specular bounces and render as fireflies. The rest of the light is path
+// traced as usual.
+//
+// NOTE: the sky and directional lights don't emit photons, so caustics lit by them are still
+// left to path tracing, which can't find those of a perfectly sharp (delta) sun.
+pub struct PhotonMapping {
+    caustics: PhotonMap,
+}
+
+impl PhotonMapping {
+    // Shoots `photon_count` photons from the scene's lights, at times spread over the
+    // `shutter` interval. Each density estimate gathers the photons within `radius`, trading
+    // noise for blur as it grows. With `spectral`, as for a spectral camera, each photon
+    // follows a single wavelength, so glass with dispersion splits caustics into colors.
+    pub fn new(
+        scene: &Scene,
+        photon_count: u32,
+        radius: f64,
+        shutter: (f64, f64),
+        spectral: bool,
+    ) -> PhotonMapping {
+        let lights = LightList::new(scene);
+        let (open, close) = shutter;
+        let photons: Vec<Photon> = (0..photon_count)
+            .into_par_iter()
+            .filter_map(|_| {
+                let time = open + (close - open) * get_random_f64();
+                let wavelength = spectral.then(sample_wavelength);
+                trace_photon(scene, &lights, time, wavelength)
+            })
+            .map(|photon| Photon {
+                power: photon.power / f64::from(photon_count),
+                ..photon
+            })
+            .collect();
+
+        PhotonMapping {
+            caustics: PhotonMap::new(photons, radius),
+        }
+    }
+}
+
+impl Integrator for PhotonMapping {
+    fn radiance(&self, ray: &Ray, context: &RenderContext, wavelength: Option<f64>) -> Color {
+        trace_path(ray, context, wavelength, false, Some(&self.caustics))
+    }
+}
+
+// Follows a photon from a randomly chosen light through specular bounces, returning it where
+// it lands on the first other surface. Photons whose first bounce isn't specular carry direct
+// light, which is sampled at render time instead, so they aren't stored.
+//
+// A photon following `wavelength` is stored with the RGB its power contributes to the film,
+// so the map can be gathered at any wavelength.
+fn trace_photon(
+    scene: &Scene,
+    lights: &LightList,
+    time: f64,
+    wavelength: Option<f64>,
+) -> Option<Photon> {
+    let (light, light_probability) = lights.choose()?;
+    let emission = light.sample_le(wavelength, time)?;
+    if emission.pdf_pos <= 0.0 || emission.pdf_dir <= 0.0 {
+        return None;
+    }
+
+    let mut ray = emission.ray;
+    ray.wavelength = wavelength;
+    let cos_theta = if emission.normal.is_near_zero() {
+        1.0
+    } else {
+        emission.normal.dot(&ray.direction.unit_vector()).abs()
+    };
+    let mut power =
+        emission.radiance * (cos_theta / (emission.pdf_pos * emission.pdf_dir * light_probability));
+
+    for depth in 0..MAX_DEPTH {
+        let record = scene.hit(&ray, 0.001..=f64::INFINITY)?;
+        let mat = record.mat.clone().unwrap();
+        let sample = mat.sample(&ray, &record);
+
+        let specular = sample.as_ref().is_some_and(|sample| sample.is_delta);
+        if !specular {
+            return (depth > 0).then(|| Photon {
+                p: record.p,
+                normal: record.normal,
+                wi: -ray.direction.unit_vector(),
+                power: match wavelength {
+                    Some(wavelength) => wavelength_to_rgb(wavelength, power.x),
+                    None => power,
+                },
+                axis: 0,
+            });
+        }
+
+        let sample = sample.unwrap();
+        power = russian_roulette(power * upsample(sample.weight, wavelength), depth)?;
+        ray = sample.ray;
+    }
+
+    None
+}
+
+#[derive(Clone, Copy)]
+struct Photon {
+    p: Point3,
+    // Normal of the surface the photon landed on, facing the way it arrived from.
+    normal: Vec3,
+    // Unit direction the photon arrived from.
+    wi: Vec3,
+    power: Color,
+    // The axis this photon splits its subtree along.
+    axis: usize,
+}
+
+// Photons stored as a balanced kd-tree, laid out in place: the median of each slice is the
+// root of its subtree, with the photons before it on one side of its splitting plane and the
+// ones after it on the other.
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    radius: f64,
+}
+
+impl PhotonMap {
+    fn new(mut photons: Vec<Photon>, radius: f64) -> PhotonMap {
+        build(&mut photons);
+        PhotonMap { photons, radius }
+    }
+
+    // Radiance reflected towards `wo` by the photons around the hit, divided by the area they
+    // were gathered from.
+    pub fn estimate(&self, wo: &Vec3, record: &HitRecord) -> Color {
+        let mat = record.mat.clone().unwrap();
+        let mut flux = Color::default();
+        search(
+            &self.photons,
+            &record.p,
+            self.radius * self.radius,
+            &mut |photon| {
+                // Leave out photons on other surfaces within the radius, such as the far side of
+                // a thin wall.
+                let cos_theta = record.normal.dot(&photon.wi);
+                if photon.normal.dot(&record.normal) < 0.5 || cos_theta <= 0.0 {
+                    return;
+                }
+                // `eval` includes the cosine of the photon's direction, which its power already
+                // accounts for.
+                flux += mat.eval(wo, &photon.wi, record) * photon.power / cos_theta;
+            },
+        );
+
+        flux / (PI * self.radius * self.radius)
+    }
+}
+
+fn build(photons: &mut [Photon]) {
+    if photons.is_empty() {
+        return;
+    }
+
+    // Split along the axis the photons are spread furthest on.
+    let mut min = photons[0].p;
+    let mut max = photons[0].p;
+    for photon in photons.iter() {
+        min = Vec3::new(
+            min.x.min(photon.p.x),
+            min.y.min(photon.p.y),
+            min.z.min(photon.p.z),
+        );
+        max = Vec3::new(
+            max.x.max(photon.p.x),
+            max.y.max(photon.p.y),
+            max.z.max(photon.p.z),
+        );
+    }
+    let extent = max - min;
+    let axis = (0..3)
+        .max_by(|&a, &b| extent[a].total_cmp(&extent[b]))
+        .unwrap();
+
+    let median = photons.len() / 2;
+    photons.select_nth_unstable_by(median, |a, b| a.p[axis].total_cmp(&b.p[axis]));
+    photons[median].axis = axis;
+
+    let (below, above) = photons.split_at_mut(median);
+    build(below);
+    build(&mut above[1..]);
+}
+
+// Calls `visit` on every photon within the square root of `radius_squared` of `p`.
+fn search(photons: &[Photon], p: &Point3, radius_squared: f64, visit: &mut dyn FnMut(&Photon)) {
+    if photons.is_empty() {
+        return;
+    }
+
+    let median = photons.len() / 2;
+    let photon = &photons[median];
+    if (photon.p - *p).length_squared() <= radius_squared {
+        visit(photon);
+    }
+
+    let offset = p[photon.axis] - photon.p[photon.axis];
+    let (near, far) = if offset < 0.0 {
+        (&photons[..median], &photons[median + 1..])
+    } else {
+        (&photons[median + 1..], &photons[..median])
+    };
+    search(near, p, radius_squared, visit);
+    if offset * offset <= radius_squared {
+        search(far, p, radius_squared, visit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::hittable::{HittableList, Triangle};
+    use crate::integrator::PathTracer;
+    use crate::light::DirectionalLight;
+    use crate::material::{Lambertian, Material, Metal};
+    use std::sync::Arc;
+
+    // Adds the parallelogram with a corner at `corner` and sides `u` and `v`.
+    fn quad(world: &mut HittableList, corner: Point3, u: Vec3, v: Vec3, mat: &Arc<dyn Material>) {
+        world.push(Box::new(Triangle::new(
+            corner,
+            corner + u,
+            corner + u + v,
+            mat.clone(),
+        )));
+        world.push(Box::new(Triangle::new(
+            corner,
+            corner + u + v,
+            corner + v,
+            mat.clone(),
+        )));
+    }
+
+    #[test]
+    fn directional_lights_reach_diffuse_surfaces_through_mirrors() {
+        // A floor under a mirror, which the sun, shining up from below the horizon, only lights
+        // through the mirror.
+        let mut world = HittableList::new();
+        let white: Arc<dyn Material> = Arc::new(Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5),
+        });
+        let mirror: Arc<dyn Material> = Arc::new(Metal {
+            albedo: Color::new(0.9, 0.9, 0.9),
+            f: 0.0,
+        });
+        quad(
+            &mut world,
+            Point3::new(1.0, 0.0, -1.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            &white,
+        );
+        quad(
+            &mut world,
+            Point3::new(-1.0, 1.5, -1.0),
+            Vec3::new(4.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            &mirror,
+        );
+        let mut scene = Scene::new(world);
+        scene.lights.push(Box::new(DirectionalLight::new(
+            Vec3::new(1.0, 1.0, 0.0),
+            Color::new(20.0, 20.0, 20.0),
+            60.0,
+        )));
+
+        let camera = Camera::new(1.0, 16)
+            .with_look_at(
+                Point3::new(1.5, 1.2, 1.8),
+                Point3::new(1.5, 0.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+            )
+            .with_vfov(60.0);
+        let mean = |integrator: &dyn Integrator| {
+            let pixels = camera.render_pixels(&scene, integrator);
+            pixels.iter().map(|pixel| pixel.luminance()).sum::<f64>() / pixels.len() as f64
+        };
+        let photons = PhotonMapping::new(&scene, 1000, 0.05, (0.0, 0.0), false);
+        let (expected, mapped) = (mean(&PathTracer), mean(&photons));
+        assert!(
+            (mapped / expected - 1.0).abs() < 0.1,
+            "{mapped} vs {expected}"
+        );
+    }
+}
@@ -4,11 +4,32 @@ use crate::vec::Point3;
 pub struct Ray {
     pub origin: Point3,
     pub direction: Point3,
+    // Wavelength in nanometres the ray is restricted to, if any. Set once a path has been
+    // split by a wavelength-dependent event such as dispersion.
+    pub wavelength: Option<f64>,
+    // Moment within the camera's shutter interval the ray samples, which moving objects are
+    // intersected at.
+    pub time: f64,
 }
 
 impl Ray {
     pub fn new(origin: Point3, direction: Point3) -> Ray {
-        Ray { origin, direction }
+        Ray {
+            origin,
+            direction,
+            wavelength: None,
+            time: 0.0,
+        }
+    }
+
+    // Creates a new ray that carries over this ray's wavelength and time.
+    pub fn spawn(&self, origin: Point3, direction: Point3) -> Ray {
+        Ray {
+            origin,
+            direction,
+            wavelength: self.wavelength,
+            time: self.time,
+        }
     }
 
     pub fn at(self, t: f64) -> Point3 {
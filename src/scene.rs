@@ -0,0 +1,70 @@
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use crate::color::Color;
+use crate::hittable::{HitRecord, Hittable, HittableList};
+use crate::light::Light;
+use crate::material::Material;
+use crate::medium::HomogeneousMedium;
+use crate::ray::Ray;
+
+// Everything the camera renders: the world's geometry, the lights illuminating it, and the
+// medium filling the space in between, if it isn't empty.
+pub struct Scene {
+    pub world: HittableList,
+    pub lights: Vec<Box<dyn Light>>,
+    pub medium: Option<HomogeneousMedium>,
+}
+
+impl Scene {
+    pub fn new(world: HittableList) -> Scene {
+        Scene {
+            world,
+            lights: Vec::new(),
+            medium: None,
+        }
+    }
+
+    // Fills the space between the scene's objects with fog of constant `density`. Every ray is
+    // attenuated and scattered on its way between surfaces, so lights cast visible shafts past
+    // the objects shadowing them. See `HomogeneousMedium` for the lighting this needs.
+    pub fn with_fog(mut self, density: f64, phase_function: Arc<dyn Material>) -> Scene {
+        self.medium = Some(HomogeneousMedium::new(density, phase_function));
+        self
+    }
+
+    // The first thing `ray` meets within `ray_t`: a surface or volume of the world, or the
+    // scene's medium, which integrators should use instead of the world's `hit`. Scattering in
+    // the medium is reported as hitting the object after the last of the world's.
+    pub fn hit(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> Option<HitRecord> {
+        let surface = self.world.hit(ray, ray_t.clone());
+        let Some(medium) = &self.medium else {
+            return surface;
+        };
+
+        let t_max = surface.as_ref().map_or(*ray_t.end(), |record| record.t);
+        medium
+            .hit(ray, *ray_t.start()..=t_max)
+            .map(|record| HitRecord {
+                object: self.world.len(),
+                ..record
+            })
+            .or(surface)
+    }
+
+    // Fraction of the light travelling along `ray` within `ray_t` that gets through the world
+    // and the scene's medium.
+    pub fn transmittance(&self, ray: &Ray, ray_t: RangeInclusive<f64>) -> f64 {
+        let world = self.world.transmittance(ray, ray_t.clone());
+        match &self.medium {
+            Some(medium) if world > 0.0 => world * medium.transmittance(ray, ray_t),
+            _ => world,
+        }
+    }
+
+    // Radiance of the sky seen by rays that escape the world.
+    pub fn background(&self, ray: &Ray) -> Color {
+        let a = 0.5 * (ray.direction.unit_vector().y + 1.0);
+        Color::new(1.0, 1.0, 1.0) * (1.0 - a) + Color::new(0.5, 0.7, 1.0) * a
+    }
+}
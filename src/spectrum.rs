@@ -0,0 +1,161 @@
+use std::sync::OnceLock;
+
+use crate::color::Color;
+use crate::utils::get_random_f64_custom;
+
+// The range of visible wavelengths (in nanometres) that spectral rendering samples from.
+pub const LAMBDA_MIN: f64 = 380.0;
+pub const LAMBDA_MAX: f64 = 720.0;
+
+pub enum Spectrum {
+    Constant(f64),
+    // An RGB colour, upsampled to a smooth spectrum with `rgb_to_spectral`.
+    Rgb(Color),
+    // Planck's law for a black body at `temperature` kelvin, scaled so its peak is `scale`.
+    Blackbody { temperature: f64, scale: f64 },
+    // (wavelength, value) pairs sorted by wavelength, linearly interpolated between samples.
+    Sampled(Vec<(f64, f64)>),
+}
+
+impl Spectrum {
+    pub fn sample(&self, wavelength: f64) -> f64 {
+        match self {
+            Spectrum::Constant(value) => *value,
+            Spectrum::Rgb(color) => rgb_to_spectral(*color, wavelength),
+            Spectrum::Blackbody { temperature, scale } => {
+                // Wien's displacement law gives the wavelength of the peak.
+                let peak = 2.897_771_955e6 / temperature;
+                scale * planck(wavelength, *temperature) / planck(peak, *temperature)
+            }
+            Spectrum::Sampled(samples) => {
+                let i = samples.partition_point(|&(lambda, _)| lambda < wavelength);
+                if i == 0 {
+                    samples.first().map_or(0.0, |&(_, value)| value)
+                } else if i == samples.len() {
+                    samples[i - 1].1
+                } else {
+                    let (l0, v0) = samples[i - 1];
+                    let (l1, v1) = samples[i];
+                    v0 + (v1 - v0) * (wavelength - l0) / (l1 - l0)
+                }
+            }
+        }
+    }
+
+    // Integrates the spectrum against the colour matching functions, so that it can be used by
+    // the RGB pipeline. A constant spectrum of 1.0 maps to white.
+    pub fn to_rgb(&self) -> Color {
+        if let Spectrum::Rgb(color) = self {
+            return *color;
+        }
+
+        let mut rgb = Color::default();
+        let mut wavelength = LAMBDA_MIN;
+        while wavelength <= LAMBDA_MAX {
+            rgb += xyz_to_linear_srgb(cie_xyz(wavelength)) * self.sample(wavelength);
+            wavelength += 1.0;
+        }
+        rgb * white_balance()
+    }
+}
+
+// Picks a wavelength uniformly over the visible range.
+pub fn sample_wavelength() -> f64 {
+    get_random_f64_custom(LAMBDA_MIN, LAMBDA_MAX)
+}
+
+// Converts a radiance estimate carried at a single uniformly sampled wavelength into its
+// contribution to a linear RGB pixel.
+pub fn wavelength_to_rgb(wavelength: f64, radiance: f64) -> Color {
+    let pdf = 1.0 / (LAMBDA_MAX - LAMBDA_MIN);
+    xyz_to_linear_srgb(cie_xyz(wavelength)) * white_balance() * (radiance / pdf)
+}
+
+// Converts the radiance a path carries into linear RGB for the film. In spectral mode
+// `radiance` is a grey holding the radiance at `wavelength`.
+pub fn film_rgb(radiance: Color, wavelength: Option<f64>) -> Color {
+    match wavelength {
+        Some(wavelength) => wavelength_to_rgb(wavelength, radiance.x),
+        None => radiance,
+    }
+}
+
+// Upsamples an RGB triple to a smooth spectrum and evaluates it at `wavelength`. The three
+// basis functions sum to one everywhere, so greys map to constant spectra and reflectances in
+// [0, 1] stay in [0, 1].
+pub fn rgb_to_spectral(color: Color, wavelength: f64) -> f64 {
+    let blue = 1.0 - smoothstep(475.0, 505.0, wavelength);
+    let red = smoothstep(570.0, 600.0, wavelength);
+    let green = 1.0 - blue - red;
+    color.x * red + color.y * green + color.z * blue
+}
+
+// Converts a color for use along a path. In spectral mode (when `wavelength` is set) colors
+// are upsampled, and carried as greys holding their value at the path's wavelength.
+pub fn upsample(color: Color, wavelength: Option<f64>) -> Color {
+    match wavelength {
+        Some(wavelength) => {
+            let value = rgb_to_spectral(color, wavelength);
+            Color::new(value, value, value)
+        }
+        None => color,
+    }
+}
+
+// Analytic multi-lobe fit of the CIE 1931 colour matching functions (Wyman, Sloan & Shirley
+// 2013).
+pub fn cie_xyz(wavelength: f64) -> Color {
+    let lobe = |mu: f64, sigma_below: f64, sigma_above: f64| {
+        let sigma = if wavelength < mu {
+            sigma_below
+        } else {
+            sigma_above
+        };
+        let t = (wavelength - mu) / sigma;
+        (-0.5 * t * t).exp()
+    };
+
+    Color::new(
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    )
+}
+
+pub fn xyz_to_linear_srgb(xyz: Color) -> Color {
+    Color::new(
+        3.240_454_2 * xyz.x - 1.537_138_5 * xyz.y - 0.498_531_4 * xyz.z,
+        -0.969_266 * xyz.x + 1.876_010_8 * xyz.y + 0.041_556 * xyz.z,
+        0.055_643_4 * xyz.x - 0.204_025_9 * xyz.y + 1.057_225_2 * xyz.z,
+    )
+}
+
+// Per-channel scale that maps the equal-energy spectrum to RGB white.
+fn white_balance() -> Color {
+    static WHITE_BALANCE: OnceLock<Color> = OnceLock::new();
+
+    *WHITE_BALANCE.get_or_init(|| {
+        let mut white = Color::default();
+        let mut wavelength = LAMBDA_MIN;
+        while wavelength <= LAMBDA_MAX {
+            white += xyz_to_linear_srgb(cie_xyz(wavelength));
+            wavelength += 1.0;
+        }
+        Color::new(1.0 / white.x, 1.0 / white.y, 1.0 / white.z)
+    })
+}
+
+// Spectral radiance of a black body, with `wavelength` in nanometres and `temperature` in
+// kelvin. Only ratios of this are used, so the constant factors are left out.
+fn planck(wavelength: f64, temperature: f64) -> f64 {
+    // Second radiation constant hc/k, in nm·K.
+    const C2: f64 = 1.438_776_877e7;
+    let lambda = wavelength * 1.0e-3;
+    1.0 / (lambda.powi(5) * ((C2 / (wavelength * temperature)).exp() - 1.0))
+}
+
+fn smoothstep(edge0: f64, edge1: f64, x: f64) -> f64 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
@@ -1,16 +1,32 @@
+use std::cell::Cell;
 use std::ops::RangeInclusive;
 
 use rand::distributions::{Distribution, Uniform};
 
-pub const PI: f64 = 3.1415926535897932385;
+pub const PI: f64 = std::f64::consts::PI;
+
+thread_local! {
+    // Stands in for the thread's random number generator while set, so that a sampler can
+    // control every random decision made along a path.
+    static RANDOM_SOURCE: Cell<Option<fn() -> f64>> = const { Cell::new(None) };
+}
+
+// Routes this thread's random numbers through `source`, or back to the random number
+// generator with None. `source` must return numbers in [0.0, 1.0).
+pub fn set_random_source(source: Option<fn() -> f64>) {
+    RANDOM_SOURCE.with(|random_source| random_source.set(source));
+}
 
 pub fn get_random_f64() -> f64 {
+    if let Some(source) = RANDOM_SOURCE.with(Cell::get) {
+        return source();
+    }
     // Range [0.0, 1.0)
     Uniform::new(0.0, 1.0).sample(&mut rand::thread_rng())
 }
 
 pub fn get_random_f64_custom(min: f64, max: f64) -> f64 {
-    Uniform::new(min, max).sample(&mut rand::thread_rng())
+    min + (max - min) * get_random_f64()
 }
 
 pub fn clamp(range: &RangeInclusive<f64>, x: f64) -> f64 {
@@ -24,3 +40,35 @@ pub fn linear_to_gamma(linear_component: f64) -> f64 {
 pub fn gamma_to_linear(linear_component: f64) -> f64 {
     linear_component * linear_component
 }
+
+// Multiple importance sampling weight for a sample drawn with density `f_pdf`, when it could
+// also have been drawn with density `g_pdf`.
+pub fn power_heuristic(f_pdf: f64, g_pdf: f64) -> f64 {
+    let f2 = f_pdf * f_pdf;
+    let g2 = g_pdf * g_pdf;
+    if f2 + g2 == 0.0 {
+        0.0
+    } else {
+        f2 / (f2 + g2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn power_heuristic_weights_sum_to_one() {
+        for (f_pdf, g_pdf) in [(1.0, 1.0), (0.3, 2.5), (4.0, 0.0), (0.0, 7.0)] {
+            let sum = power_heuristic(f_pdf, g_pdf) + power_heuristic(g_pdf, f_pdf);
+            assert!(
+                (sum - 1.0).abs() < 1e-12,
+                "Weights of {f_pdf} and {g_pdf} sum to {sum}"
+            );
+        }
+        assert_eq!(power_heuristic(1.0, 1.0), 0.5);
+        assert_eq!(power_heuristic(3.0, 1.0), 0.9);
+        // A sample neither strategy could have drawn gets no weight, rather than NaN.
+        assert_eq!(power_heuristic(0.0, 0.0), 0.0);
+    }
+}
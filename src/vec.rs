@@ -1,7 +1,6 @@
-use std::cmp::min;
-use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+use std::ops::{Add, AddAssign, Div, DivAssign, Index, Mul, MulAssign, Neg, Sub, SubAssign};
 
-use crate::utils::{get_random_f64, get_random_f64_custom};
+use crate::utils::{get_random_f64, PI};
 
 #[derive(Clone, Copy, Debug)]
 pub struct Vec3 {
@@ -54,6 +53,19 @@ impl DivAssign<f64> for Vec3 {
     }
 }
 
+// Components by axis: 0 is x, 1 is y and 2 is z.
+impl Index<usize> for Vec3 {
+    type Output = f64;
+    fn index(&self, axis: usize) -> &f64 {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 has no axis {axis}"),
+        }
+    }
+}
+
 impl Mul<f64> for Vec3 {
     type Output = Vec3;
 
@@ -130,25 +142,42 @@ impl Vec3 {
         )
     }
 
-    fn random_custom(min: f64, max: f64) -> Self {
-        Self::new(
-            get_random_f64_custom(min, max),
-            get_random_f64_custom(min, max),
-            get_random_f64_custom(min, max),
-        )
+    // Returns a random direction, uniformly distributed over the sphere. Like the other
+    // sampling functions it warps a fixed number of random numbers rather than rejecting
+    // some, so a sampler that perturbs them (as Metropolis does) moves the direction smoothly.
+    pub fn random_unit_vector() -> Self {
+        let z = 1.0 - 2.0 * get_random_f64();
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * get_random_f64();
+        Self::new(r * phi.cos(), r * phi.sin(), z)
     }
 
-    fn random_in_unit_sphere() -> Self {
-        loop {
-            let p = Self::random_custom(-1.0, 1.0);
-            if p.length_squared() < 1.0 {
-                return p;
-            }
-        }
+    // Returns a random direction in the local frame around +z, distributed proportionally to
+    // the cosine of its angle with +z.
+    pub fn random_cosine_direction() -> Self {
+        let r1 = get_random_f64();
+        let r2 = get_random_f64();
+
+        let phi = 2.0 * PI * r1;
+        let x = phi.cos() * r2.sqrt();
+        let y = phi.sin() * r2.sqrt();
+        let z = (1.0 - r2).sqrt();
+
+        Self::new(x, y, z)
     }
 
-    pub fn random_unit_vector() -> Self {
-        Self::random_in_unit_sphere().unit_vector()
+    // Returns a random direction in the local frame around +z, uniformly distributed over the
+    // cone subtended by a sphere of `radius` whose center lies `distance_squared` away along +z.
+    pub fn random_to_sphere(radius: f64, distance_squared: f64) -> Self {
+        let r1 = get_random_f64();
+        let r2 = get_random_f64();
+        let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).max(0.0).sqrt() - 1.0);
+
+        let phi = 2.0 * PI * r1;
+        let x = phi.cos() * (1.0 - z * z).sqrt();
+        let y = phi.sin() * (1.0 - z * z).sqrt();
+
+        Self::new(x, y, z)
     }
 
     pub fn is_near_zero(&self) -> bool {